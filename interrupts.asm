@@ -0,0 +1,46 @@
+; Counts up on the LEDs four times a second from a timer interrupt, while the main loop does nothing.
+; Pressing any button clears the count. Run its tests with `cargo run test interrupts.asm`.
+;
+;! test timer_interrupt_counts
+;!   set [LED_ADDR] 5
+;!   set [INT_CAUSE_ADDR] 0x0002
+;!   call .interrupt_handler
+;!   expect [LED_ADDR] 6
+;!   expect [INT_CAUSE_ADDR] 0
+;! end
+;!
+;! test button_interrupt_clears_count
+;!   set [LED_ADDR] 5
+;!   set [INT_CAUSE_ADDR] 0x0003   # a button press and the timer at once
+;!   call .interrupt_handler
+;!   expect [LED_ADDR] 0
+;!   expect [INT_CAUSE_ADDR] 0
+;! end
+
+mov r0 0x500
+mov r1 .interrupt_handler
+mov [INT_VECTOR_ADDR] r1
+mov r1 250
+mov [TIMER_RELOAD_ADDR] r1
+mov [TIMER_COUNTDOWN_ADDR] r1
+ei
+
+.idle
+jmp .idle
+
+.interrupt_handler
+  ; only uses r13 and r14, which the rest of the program leaves alone
+  mov r13 [INT_CAUSE_ADDR]
+  ; storing the causes that were read clears them, but not one that was raised since
+  mov [INT_CAUSE_ADDR] r13
+  mov r14 r13
+  and r14 0x0001
+  jnz .button_pressed r14
+  mov r14 [LED_ADDR]
+  add r14 1
+  mov [LED_ADDR] r14
+  iret
+  .button_pressed
+  mov r14 0
+  mov [LED_ADDR] r14
+iret
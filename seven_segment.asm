@@ -0,0 +1,62 @@
+; Counts up on the seven-segment display, once per second.
+; Run its tests with `cargo run test seven_segment.asm`.
+;
+;! test hex_digits_with_decimal_point
+;!   set r1 0x12AB
+;!   set r2 0x0040   # decimal point of digit 2
+;!   call .show_hex
+;!   expect segments 12.AB
+;! end
+;!
+;! test blanked_digits
+;!   set r1 0x0042
+;!   set r2 0x000C   # blank digits 2 and 3
+;!   call .show_hex
+;!   expect segments __42
+;! end
+;!
+;! test raw_segments
+;!   set r1 0xEF3F   # digit 1 is 9 with its decimal point, digit 0 is 0
+;!   set r2 0x4000   # digit 3 is a dash, digit 2 is blank
+;!   call .show_raw
+;!   expect segments -_9.0
+;!   expect [SEGMENT_DISP_HI_ADDR] 0xC000
+;! end
+;!
+;! test wait_one_second
+;!   call .wait_one_second
+;!   expect [TIMER_TICKS_ADDR] 1000
+;!   expect [TIMER_COUNTDOWN_ADDR] 0
+;! end
+
+mov r0 0x500
+mov r1 0
+mov r2 0
+
+.count_loop
+  call .show_hex
+  call .wait_one_second
+  add r1 1
+jmp .count_loop
+
+.show_hex
+  ; parameters: r1 = value to show as 4 hex digits, r2 = blanked digits and decimal points
+  mov [SEGMENT_DISP_LO_ADDR] r1
+  mov [SEGMENT_DISP_HI_ADDR] r2
+ret
+
+.show_raw
+  ; parameters: r1 = segments of digits 1 and 0, r2 = segments of digits 3 and 2
+  mov [SEGMENT_DISP_LO_ADDR] r1
+  or r2 0x8000
+  mov [SEGMENT_DISP_HI_ADDR] r2
+ret
+
+.wait_one_second
+  ; the countdown runs in hardware time, so this takes a second whatever the clock settings
+  mov r3 1000
+  mov [TIMER_COUNTDOWN_ADDR] r3
+  .wait_loop
+    mov r3 [TIMER_COUNTDOWN_ADDR]
+  jnz .wait_loop r3
+ret
@@ -0,0 +1,773 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::disassembler::symbolize;
+use crate::emu::{CpuEmu, StopReason};
+use crate::hardware::{HardwareProfile, OutOfRange};
+use crate::instr_repr::{Reg, Verb};
+use crate::label_resolver::resolve_address;
+use crate::program::{read_file, Program};
+use crate::seven_segment::{describe, parse_display};
+use crate::tokens::convert_str_to_reg;
+
+// instructions a test may run when it does not set its own `budget`
+pub const DEFAULT_BUDGET: u64 = 100_000;
+
+// R0 is the stack pointer. Tests start with it where conn_4 puts it, above the global variables.
+const DEFAULT_STACK_ADDR: i16 = 0x500;
+
+// the return address pushed by a test's `call`. Returning to it ends the test.
+const RETURN_SENTINEL: u16 = 0xFFFE;
+
+// mismatched words listed for a single memory expectation, e.g. when a whole screen differs
+const MAX_MEM_MISMATCHES: usize = 16;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Setup {
+    Reg(Reg, i16),
+    Mem(u16, Vec<i16>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Entry {
+    // call a subroutine and run until it returns
+    Call(u16),
+    // run the whole program from its entry point until it halts
+    Run,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Expectation {
+    Reg(Reg, i16),
+    Mem(u16, Vec<i16>),
+    Ip(u16),
+    Halted,
+    // segment patterns of the seven-segment display, digit 0 first
+    Segments([u8; 4]),
+    // rows of `#` (white) and `.` (black) pixels, with the top left corner at x, y
+    Vga {
+        x: usize,
+        y: usize,
+        rows: Vec<String>,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TestCase {
+    pub name: String,
+    // where the test starts, e.g. `conn_4.tests:12`
+    pub location: String,
+    setup: Vec<Setup>,
+    entry: Entry,
+    budget: Option<u64>,
+    expectations: Vec<Expectation>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TestFileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TestFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+pub fn embedded_tests(asm_source: &str) -> String {
+    // tests can also live in the .asm file itself, on comment lines starting with `;!`.
+    // Other lines are kept empty, so that line numbers still match the .asm file.
+    asm_source
+        .lines()
+        .map(|line| line.trim_start().strip_prefix(";!").unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn parse_tests(
+    contents: &str,
+    file_name: &str,
+    program: &Program,
+) -> Result<Vec<TestCase>, TestFileError> {
+    let mut tests = Vec::new();
+    let mut current: Option<TestCase> = None;
+    let mut lines = contents.lines().enumerate();
+
+    while let Some((line_idx, line)) = lines.next() {
+        let err = |message: String| TestFileError {
+            line: line_idx + 1,
+            message,
+        };
+        let code = match line.find('#') {
+            Some(comment_start) => &line[..comment_start],
+            None => line,
+        };
+        let words: Vec<&str> = code.split_whitespace().collect();
+        let Some((keyword, args)) = words.split_first() else {
+            continue;
+        };
+
+        if *keyword == "test" {
+            if current.is_some() {
+                return Err(err("missing `end` before the next test".to_string()));
+            }
+            let [name] = args else {
+                return Err(err("expected `test NAME`".to_string()));
+            };
+            current = Some(TestCase {
+                name: name.to_string(),
+                location: format!("{}:{}", file_name, line_idx + 1),
+                setup: Vec::new(),
+                entry: Entry::Run,
+                budget: None,
+                expectations: Vec::new(),
+            });
+            continue;
+        }
+
+        let Some(test) = current.as_mut() else {
+            return Err(err(format!("`{}` outside of a test", keyword)));
+        };
+        match (*keyword, args) {
+            ("end", []) => tests.push(current.take().unwrap()),
+            ("set", [target, values @ ..]) if !values.is_empty() => {
+                let setup = match parse_target(target, program).map_err(err)? {
+                    Target::Reg(reg) => match values {
+                        [value] => Setup::Reg(reg, parse_value(value, program).map_err(err)?),
+                        _ => return Err(err("a register takes a single value".to_string())),
+                    },
+                    Target::Mem(addr, len) => {
+                        Setup::Mem(addr, parse_values(values, len, program).map_err(err)?)
+                    }
+                    Target::Ip => {
+                        return Err(err("use `call` to choose where a test starts".to_string()))
+                    }
+                };
+                test.setup.push(setup);
+            }
+            ("call", [location]) => {
+                let addr = resolve_address(location, &program.label_map, &program.var_loc_map)
+                    .ok_or(err(format!("unknown location `{}`", location)))?;
+                test.entry = Entry::Call(addr);
+            }
+            ("run", []) => test.entry = Entry::Run,
+            ("budget", [n]) => {
+                let n = n
+                    .parse()
+                    .map_err(|_| err(format!("invalid budget `{}`", n)))?;
+                test.budget = Some(n);
+            }
+            ("expect", ["halted"]) => test.expectations.push(Expectation::Halted),
+            ("expect", ["segments", digits]) => {
+                let patterns = parse_display(digits).map_err(err)?;
+                test.expectations.push(Expectation::Segments(patterns));
+            }
+            ("expect", ["vga", x, y]) => {
+                let parse = |v: &str| {
+                    v.parse::<usize>()
+                        .map_err(|_| err(format!("invalid coordinate `{}`", v)))
+                };
+                let (x, y) = (parse(x)?, parse(y)?);
+                let mut rows = Vec::new();
+                loop {
+                    let Some((_, row)) = lines.next() else {
+                        return Err(err("missing `end` after the expected pixels".to_string()));
+                    };
+                    let row = row.trim();
+                    if row == "end" {
+                        break;
+                    }
+                    if x + row.len() > 160 || row.chars().any(|c| c != '#' && c != '.') {
+                        return Err(err(format!("invalid pixel row `{}`", row)));
+                    }
+                    rows.push(row.to_string());
+                }
+                if y + rows.len() > 120 {
+                    return Err(err(
+                        "expected pixels go past the bottom of the screen".to_string()
+                    ));
+                }
+                test.expectations.push(Expectation::Vga { x, y, rows });
+            }
+            ("expect", [target, values @ ..]) if !values.is_empty() => {
+                let expectation = match parse_target(target, program).map_err(err)? {
+                    Target::Reg(reg) => match values {
+                        [value] => Expectation::Reg(reg, parse_value(value, program).map_err(err)?),
+                        _ => return Err(err("a register takes a single value".to_string())),
+                    },
+                    Target::Mem(addr, len) => {
+                        Expectation::Mem(addr, parse_values(values, len, program).map_err(err)?)
+                    }
+                    Target::Ip => match values {
+                        [location] => Expectation::Ip(
+                            resolve_address(location, &program.label_map, &program.var_loc_map)
+                                .ok_or(err(format!("unknown location `{}`", location)))?,
+                        ),
+                        _ => return Err(err("expected `expect ip LOCATION`".to_string())),
+                    },
+                };
+                test.expectations.push(expectation);
+            }
+            _ => return Err(err(format!("invalid statement `{}`", code.trim()))),
+        }
+    }
+
+    if current.is_some() {
+        return Err(TestFileError {
+            line: contents.lines().count(),
+            message: "missing `end` at the end of the file".to_string(),
+        });
+    }
+    Ok(tests)
+}
+
+enum Target {
+    Reg(Reg),
+    // the start address, and the length when a range like `[0x10:0x1F]` was given
+    Mem(u16, Option<usize>),
+    Ip,
+}
+
+fn parse_target(s: &str, program: &Program) -> Result<Target, String> {
+    // a register, `ip`, or memory in brackets like `[COLUMN_3_ADDR]` or `[VGA_BEGIN_ADDR:0x9]`
+    if s == "ip" || s == "IP" {
+        return Ok(Target::Ip);
+    }
+    if let Some(reg) = convert_str_to_reg(s) {
+        return Ok(Target::Reg(reg));
+    }
+    let location = s
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .ok_or(format!("expected a register or `[ADDRESS]`, found `{}`", s))?;
+    let resolve = |v: &str| {
+        resolve_address(v, &program.label_map, &program.var_loc_map)
+            .ok_or(format!("unknown location `{}`", v))
+    };
+    match location.split_once(':') {
+        Some((start, end)) => {
+            let (start, end) = (resolve(start)?, resolve(end)?);
+            if start > end {
+                return Err(format!(
+                    "range start 0x{:X} is after its end 0x{:X}",
+                    start, end
+                ));
+            }
+            Ok(Target::Mem(start, Some((end - start) as usize + 1)))
+        }
+        None => Ok(Target::Mem(resolve(location)?, None)),
+    }
+}
+
+fn parse_value(s: &str, program: &Program) -> Result<i16, String> {
+    // a number, which may be negative, or a label or location name standing for its address
+    if let Some(magnitude) = s.strip_prefix('-') {
+        return parse_negative(magnitude).ok_or(format!("invalid value `{}`", s));
+    }
+    resolve_address(s, &program.label_map, &program.var_loc_map)
+        .map(|v| v as i16)
+        .ok_or(format!("invalid value `{}`", s))
+}
+
+fn parse_negative(magnitude: &str) -> Option<i16> {
+    // decimal or hex, down to -32768
+    if magnitude.starts_with(['+', '-']) {
+        return None;
+    }
+    let magnitude = match magnitude.strip_prefix("0x") {
+        Some(hex_digits) => i64::from_str_radix(hex_digits, 16).ok()?,
+        None => magnitude.parse::<i64>().ok()?,
+    };
+    i16::try_from(-magnitude).ok()
+}
+
+fn parse_values(
+    values: &[&str],
+    len: Option<usize>,
+    program: &Program,
+) -> Result<Vec<i16>, String> {
+    // a range takes one value per word, or a single value for all of them
+    let values = values
+        .iter()
+        .map(|v| parse_value(v, program))
+        .collect::<Result<Vec<_>, _>>()?;
+    match len {
+        Some(len) if values.len() == 1 => Ok(vec![values[0]; len]),
+        Some(len) if values.len() != len => Err(format!(
+            "the range has {} words, but {} values were given",
+            len,
+            values.len()
+        )),
+        _ => Ok(values),
+    }
+}
+
+pub fn run_test(
+    test: &TestCase,
+    program: &Program,
+    default_budget: u64,
+    profile: HardwareProfile,
+) -> Vec<String> {
+    // returns the reasons the test failed, so an empty list means it passed
+    let mut cpu_emulator = CpuEmu::new(program.code.clone());
+    cpu_emulator.set_profile(profile, OutOfRange::Fault);
+    cpu_emulator.capture_output();
+    cpu_emulator.set_reg(Reg::R0, DEFAULT_STACK_ADDR);
+    for setup in &test.setup {
+        match setup {
+            Setup::Reg(reg, value) => cpu_emulator.set_reg(*reg, *value),
+            Setup::Mem(addr, values) => {
+                for (i, value) in values.iter().enumerate() {
+                    cpu_emulator.set_mem(addr.wrapping_add(i as u16), *value);
+                }
+            }
+        }
+    }
+
+    let budget = test.budget.unwrap_or(default_budget);
+    let stop = match test.entry {
+        Entry::Call(addr) => {
+            // push a return address like `call` would, and stop when it is returned to
+            let sp = cpu_emulator.get_regs()[0];
+            cpu_emulator.set_mem(sp as u16, RETURN_SENTINEL as i16);
+            cpu_emulator.set_reg(Reg::R0, sp.wrapping_add(1));
+            cpu_emulator.set_ip(addr);
+
+            let mut depth = 0;
+            cpu_emulator.run_until(budget, |_, event| {
+                match event.verb {
+                    Verb::Call(_) => depth += 1,
+                    Verb::Ret | Verb::Iret if depth == 0 => return true,
+                    Verb::Ret | Verb::Iret => depth -= 1,
+                    _ => {}
+                }
+                false
+            })
+        }
+        Entry::Run => cpu_emulator.run(budget),
+    };
+
+    let ip = cpu_emulator.get_ip();
+    match stop {
+        Ok(StopReason::Condition) | Ok(StopReason::Halted) => {}
+        Ok(StopReason::BudgetExhausted) => {
+            return vec![format!(
+                "did not finish within {} instructions, stopped at 0x{:0>4X} ({})",
+                budget,
+                ip,
+                symbolize(ip, &program.label_map)
+            )]
+        }
+        Ok(stop) => return vec![format!("stopped unexpectedly: {:?}", stop)],
+        Err(fault) => return vec![format!("fault: {}", fault)],
+    }
+
+    let names = addr_to_name_map(&program.var_loc_map);
+    let mut failures = Vec::new();
+    for expectation in &test.expectations {
+        match expectation {
+            Expectation::Reg(reg, expected) => {
+                let found = cpu_emulator.get_regs()[reg.to_id() as usize];
+                if found != *expected {
+                    failures.push(format!(
+                        "{}: expected {}, found {}",
+                        reg,
+                        format_word(*expected),
+                        format_word(found)
+                    ));
+                }
+            }
+            Expectation::Mem(start, values) => {
+                let mismatches: Vec<String> = values
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, expected)| {
+                        let addr = start.wrapping_add(i as u16);
+                        let found = cpu_emulator.get_mem()[addr as usize];
+                        if found == *expected {
+                            return None;
+                        }
+                        let name = names
+                            .get(&addr)
+                            .map(|n| format!(" {}", n))
+                            .unwrap_or_default();
+                        Some(format!(
+                            "[0x{:0>4X}]{}: expected {}, found {}",
+                            addr,
+                            name,
+                            format_word(*expected),
+                            format_word(found)
+                        ))
+                    })
+                    .collect();
+                failures.extend(mismatches.iter().take(MAX_MEM_MISMATCHES).cloned());
+                if mismatches.len() > MAX_MEM_MISMATCHES {
+                    failures.push(format!(
+                        "... and {} more mismatched words",
+                        mismatches.len() - MAX_MEM_MISMATCHES
+                    ));
+                }
+            }
+            Expectation::Ip(expected) => {
+                if ip != *expected {
+                    failures.push(format!(
+                        "IP: expected 0x{:0>4X} ({}), found 0x{:0>4X} ({})",
+                        expected,
+                        symbolize(*expected, &program.label_map),
+                        ip,
+                        symbolize(ip, &program.label_map)
+                    ));
+                }
+            }
+            Expectation::Halted => {
+                if stop != Ok(StopReason::Halted) {
+                    failures.push("expected the program to halt, but it returned".to_string());
+                }
+            }
+            Expectation::Segments(expected) => {
+                let found = cpu_emulator.get_seven_segment();
+                if found != *expected {
+                    failures.push(format!(
+                        "seven-segment display: expected {} ({}), found {} ({})",
+                        describe(expected),
+                        format_patterns(expected),
+                        describe(&found),
+                        format_patterns(&found)
+                    ));
+                }
+            }
+            Expectation::Vga { x, y, rows } => {
+                let found = vga_rows(cpu_emulator.get_gfx_buffer(), *x, *y, rows);
+                if found != *rows {
+                    let width = rows.iter().map(String::len).max().unwrap_or(0).max(8);
+                    let mut diff = format!(
+                        "VGA pixels at ({}, {}) do not match:\n  {:<width$}  found\n",
+                        x, y, "expected",
+                    );
+                    for (expected_row, found_row) in rows.iter().zip(&found) {
+                        let marker = if expected_row == found_row { " " } else { "!" };
+                        diff.push_str(&format!(
+                            "{} {:<width$}  {}\n",
+                            marker, expected_row, found_row
+                        ));
+                    }
+                    failures.push(diff.trim_end().to_string());
+                }
+            }
+        }
+    }
+    failures
+}
+
+fn vga_rows(gfx_buf: &[i16], x: usize, y: usize, expected: &[String]) -> Vec<String> {
+    // the screen is 160x120, 16 pixels per word with the leftmost in the top bit
+    expected
+        .iter()
+        .enumerate()
+        .map(|(row_idx, row)| {
+            (0..row.len())
+                .map(|col_idx| {
+                    let (px, py) = (x + col_idx, y + row_idx);
+                    let word = gfx_buf[py * 10 + px / 16];
+                    if (word >> (15 - px % 16)) & 0x01 == 1 {
+                        '#'
+                    } else {
+                        '.'
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn addr_to_name_map(var_loc_map: &HashMap<String, u16>) -> HashMap<u16, &str> {
+    let mut res: HashMap<u16, &str> = HashMap::new();
+    for (name, addr) in var_loc_map {
+        match res.get(addr) {
+            Some(existing) if *existing <= name.as_str() => {}
+            _ => {
+                res.insert(*addr, name);
+            }
+        }
+    }
+    res
+}
+
+fn format_patterns(patterns: &[u8; 4]) -> String {
+    // leftmost digit first, like the display reads
+    let patterns: Vec<String> = patterns
+        .iter()
+        .rev()
+        .map(|p| format!("{:0>2X}", p))
+        .collect();
+    patterns.join(" ")
+}
+
+fn format_word(value: i16) -> String {
+    format!("0x{:0>4X} ({})", value as u16, value)
+}
+
+pub fn load_tests(
+    asm_path: &str,
+    tests_path: Option<&str>,
+    program: &Program,
+) -> Result<Vec<TestCase>, String> {
+    // tests come from the given file, or else `<name>.tests` next to the .asm file if there is
+    // one, followed by the tests embedded in the .asm file itself
+    let sidecar = Path::new(asm_path).with_extension("tests");
+    let tests_path = match tests_path {
+        Some(path) => Some(path.to_string()),
+        None if sidecar.exists() => Some(sidecar.to_string_lossy().into_owned()),
+        None => None,
+    };
+
+    let mut tests = Vec::new();
+    if let Some(tests_path) = tests_path {
+        let contents = read_file(&tests_path)?;
+        tests.extend(
+            parse_tests(&contents, &tests_path, program)
+                .map_err(|e| format!("{}: {}", tests_path, e))?,
+        );
+    }
+    let asm_source = read_file(asm_path)?;
+    tests.extend(
+        parse_tests(&embedded_tests(&asm_source), asm_path, program)
+            .map_err(|e| format!("{}: {}", asm_path, e))?,
+    );
+    Ok(tests)
+}
+
+pub fn run_tests(
+    tests: &[TestCase],
+    program: &Program,
+    default_budget: u64,
+    profile: HardwareProfile,
+) -> bool {
+    // prints a report like `cargo test` does, and returns whether every test passed
+    println!(
+        "running {} test{}",
+        tests.len(),
+        if tests.len() == 1 { "" } else { "s" }
+    );
+    let mut failed = Vec::new();
+    for test in tests {
+        let failures = run_test(test, program, default_budget, profile);
+        if failures.is_empty() {
+            println!("test {} ... ok", test.name);
+        } else {
+            println!("test {} ... FAILED", test.name);
+            failed.push((test, failures));
+        }
+    }
+
+    if !failed.is_empty() {
+        println!("\nfailures:");
+        for (test, failures) in &failed {
+            println!("\n---- {} ({}) ----", test.name, test.location);
+            for failure in failures {
+                println!("{}", failure);
+            }
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        if failed.is_empty() { "ok" } else { "FAILED" },
+        tests.len() - failed.len(),
+        failed.len()
+    );
+    failed.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::label_resolver::resolve_labels;
+    use crate::tokens::get_tokens;
+
+    const SOURCE: &str = "\
+mov r1 1
+call .quad
+mov [RESULT] r1
+halt
+.quad
+  call .double
+  call .double
+  ret
+.double
+  add r1 r1
+  ret
+.spin
+  jmp .spin
+;! test runs_to_halt
+;!   run
+;!   expect halted
+;!   expect [RESULT] 4
+;! end
+";
+
+    fn program() -> Program {
+        let var_loc_map = HashMap::from([("RESULT".to_string(), 0x4C0)]);
+        let ((mut verbs, label_map, spans), errors) = get_tokens(SOURCE.to_string(), &var_loc_map);
+        assert!(errors.is_empty());
+        resolve_labels(&mut verbs, &label_map, &spans).unwrap();
+        Program {
+            code: verbs.iter().map(Verb::to_bytes).collect(),
+            label_map,
+            var_loc_map,
+            spans,
+        }
+    }
+
+    fn run(tests: &str) -> Vec<String> {
+        let program = program();
+        let tests = parse_tests(tests, "prog.tests", &program).unwrap();
+        run_test(&tests[0], &program, DEFAULT_BUDGET, HardwareProfile::Basys3)
+    }
+
+    fn parse_error(tests: &str) -> String {
+        parse_tests(tests, "prog.tests", &program())
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn sidecar_tests_come_before_embedded_ones() {
+        let dir = std::env::temp_dir().join(format!("asm_emu_asm_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let asm_path = dir.join("prog.asm");
+        std::fs::write(&asm_path, SOURCE).unwrap();
+        let asm_path = asm_path.to_str().unwrap();
+        let program = program();
+
+        // without a sidecar file only the embedded tests are found
+        let tests = load_tests(asm_path, None, &program).unwrap();
+        let names: Vec<_> = tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["runs_to_halt"]);
+        assert_eq!(tests[0].location, format!("{}:14", asm_path));
+
+        let sidecar = dir.join("prog.tests");
+        std::fs::write(&sidecar, "\ntest doubles\n  call .double\nend\n").unwrap();
+        let tests = load_tests(asm_path, None, &program).unwrap();
+        let names: Vec<_> = tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["doubles", "runs_to_halt"]);
+        assert_eq!(
+            tests[0].location,
+            format!("{}:2", sidecar.to_str().unwrap())
+        );
+        assert_eq!(tests[0].entry, Entry::Call(7));
+
+        // --tests replaces the sidecar file
+        let other = dir.join("other.tests");
+        std::fs::write(&other, "test other\nend\n").unwrap();
+        let tests = load_tests(asm_path, other.to_str(), &program).unwrap();
+        let names: Vec<_> = tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["other", "runs_to_halt"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn malformed_tests_are_rejected() {
+        assert_eq!(
+            parse_error("expect r1 1\n"),
+            "line 1: `expect` outside of a test"
+        );
+        assert_eq!(
+            parse_error("test a\ntest b\n"),
+            "line 2: missing `end` before the next test"
+        );
+        assert_eq!(
+            parse_error("test a\n  run\n"),
+            "line 2: missing `end` at the end of the file"
+        );
+        assert_eq!(
+            parse_error("test a\n  expect r1\nend\n"),
+            "line 2: invalid statement `expect r1`"
+        );
+        assert_eq!(
+            parse_error("test a\n  expect r1 1 2\nend\n"),
+            "line 2: a register takes a single value"
+        );
+        assert_eq!(
+            parse_error("test a\n  expect [RESULT:0x4C2] 1 2\nend\n"),
+            "line 2: the range has 3 words, but 2 values were given"
+        );
+        assert_eq!(
+            parse_error("test a\n  expect r1 -40000\nend\n"),
+            "line 2: invalid value `-40000`"
+        );
+        assert_eq!(
+            parse_error("test a\n  expect ip .nowhere\nend\n"),
+            "line 2: unknown location `.nowhere`"
+        );
+        assert_eq!(
+            parse_error("test a\n  set ip .double\nend\n"),
+            "line 2: use `call` to choose where a test starts"
+        );
+        assert_eq!(
+            parse_error("test a\n  expect vga 0 0\n  #x#\n  end\nend\n"),
+            "line 2: invalid pixel row `#x#`"
+        );
+        assert_eq!(
+            parse_error("test a\n  expect vga 0 0\n  ##\n"),
+            "line 2: missing `end` after the expected pixels"
+        );
+    }
+
+    #[test]
+    fn a_call_returns_to_the_sentinel() {
+        // .quad calls .double twice, only its own ret ends the test, just past the sentinel
+        let failures = run("test quad\n  set r1 3\n  call .quad\n  expect r1 12\n  \
+                            expect r0 0x500\n  expect ip 0xFFFF\nend\n");
+        assert_eq!(failures, Vec::<String>::new());
+        assert!(run("test halts\n  run\n  expect halted\n  expect [RESULT] 4\nend\n").is_empty());
+        assert_eq!(
+            run("test quad\n  call .quad\n  expect halted\nend\n"),
+            ["expected the program to halt, but it returned"]
+        );
+    }
+
+    #[test]
+    fn running_out_of_budget_fails() {
+        assert_eq!(
+            run("test spin\n  call .spin\n  budget 50\nend\n"),
+            ["did not finish within 50 instructions, stopped at 0x0009 (.spin)"]
+        );
+    }
+
+    #[test]
+    fn failed_expectations_show_what_was_found() {
+        let failures = run("test quad\n  set r1 3\n  call .quad\n  expect r1 -12\n  \
+                            expect [RESULT:0x4C1] 1 0\n  expect ip .quad\n  \
+                            expect segments 00-0\n  expect vga 14 0\n    ..##..\n    end\nend\n");
+        assert_eq!(
+            failures,
+            [
+                "R1: expected 0xFFF4 (-12), found 0x000C (12)",
+                "[0x04C0] RESULT: expected 0x0001 (1), found 0x0000 (0)",
+                "IP: expected 0x0004 (.quad), found 0xFFFF (.spin+65526)",
+                "seven-segment display: expected 00-0 (3F 3F 40 3F), found 0000 (3F 3F 3F 3F)",
+                "VGA pixels at (14, 0) do not match:\n  \
+                 expected  found\n\
+                 ! ..##..    ......",
+            ]
+        );
+    }
+
+    #[test]
+    fn negative_values_cover_the_whole_i16_range() {
+        assert_eq!(parse_negative("1"), Some(-1));
+        assert_eq!(parse_negative("32768"), Some(i16::MIN));
+        assert_eq!(parse_negative("0x10"), Some(-16));
+        assert_eq!(parse_negative("0x8000"), Some(i16::MIN));
+        assert_eq!(parse_negative("0"), Some(0));
+        assert_eq!(parse_negative("32769"), None);
+        assert_eq!(parse_negative("0x8001"), None);
+        assert_eq!(parse_negative("-1"), None);
+        assert_eq!(parse_negative("+1"), None);
+        assert_eq!(parse_negative("0x"), None);
+        assert_eq!(parse_negative("abc"), None);
+    }
+}
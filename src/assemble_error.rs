@@ -0,0 +1,139 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    // 1-based line, 0-based column, both counted in chars like SourceCodeCursor
+    pub line: usize,
+    pub col: usize,
+    pub len: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, col: usize, len: usize) -> Self {
+        Span {
+            line,
+            col,
+            len: len.max(1),
+        }
+    }
+
+    pub fn to(&self, other: Span) -> Span {
+        // joins two spans on the same line, e.g. the first and last operand of an instruction
+        if other.line != self.line || other.col + other.len < self.col {
+            return *self;
+        }
+        Span::new(self.line, self.col, other.col + other.len - self.col)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AssembleError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl AssembleError {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        AssembleError {
+            message: message.into(),
+            span,
+        }
+    }
+
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        // formats the error like rustc does:
+        //
+        // error: invalid operands for mov
+        //  --> conn_4.asm:3:5
+        //   |
+        // 3 | mov 5 r1
+        //   |     ^^^^
+        let line_text = source
+            .lines()
+            .nth(self.span.line.saturating_sub(1))
+            .unwrap_or("");
+        let line_num = self.span.line.to_string();
+        let gutter = " ".repeat(line_num.len());
+
+        // keep tabs in the caret line, so that the carets line up with the source line
+        let caret_indent: String = line_text
+            .chars()
+            .take(self.span.col)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        format!(
+            "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+            self.message,
+            gutter,
+            file_name,
+            self.span.line,
+            self.span.col + 1,
+            gutter,
+            line_num,
+            line_text,
+            gutter,
+            caret_indent,
+            "^".repeat(self.span.len),
+        )
+    }
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.line,
+            self.span.col + 1,
+            self.message
+        )
+    }
+}
+
+pub fn render_errors(errors: &[AssembleError], file_name: &str, source: &str) -> String {
+    let mut errors = errors.to_vec();
+    errors.sort_by_key(|e| (e.span.line, e.span.col));
+
+    let mut res = String::new();
+    for error in &errors {
+        res.push_str(&error.render(file_name, source));
+        res.push('\n');
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_render_like_rustc() {
+        let source = "mov r1 5\n\tadd r1 [r99]\n";
+        let error = AssembleError::new("bad operand", Span::new(2, 8, 5));
+        assert_eq!(
+            error.render("prog.asm", source),
+            "error: bad operand\n --> prog.asm:2:9\n  |\n2 | \tadd r1 [r99]\n  | \t       ^^^^^\n"
+        );
+        assert_eq!(error.to_string(), "2:9: bad operand");
+
+        // the gutter grows with the line number, and an empty span still gets a caret
+        let source = "\n".repeat(9) + "jmp";
+        let error = AssembleError::new("not enough operands", Span::new(10, 3, 0));
+        assert_eq!(
+            error.render("prog.asm", &source),
+            "error: not enough operands\n  --> prog.asm:10:4\n   |\n10 | jmp\n   |    ^\n"
+        );
+    }
+
+    #[test]
+    fn errors_are_rendered_in_source_order() {
+        let source = "a\nb\n";
+        let errors = [
+            AssembleError::new("second", Span::new(2, 0, 1)),
+            AssembleError::new("first", Span::new(1, 0, 1)),
+        ];
+        let rendered = render_errors(&errors, "prog.asm", source);
+        assert!(rendered.find("first").unwrap() < rendered.find("second").unwrap());
+    }
+}
@@ -0,0 +1,187 @@
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+// A memory-mapped device. Loads and stores by the program to the device's addresses go through
+// its hooks instead of straight to data memory.
+//
+// The hooks get the data memory, which is where devices keep the state the program can see,
+// so that saved machine states, snapshots and reverse debugging cover it without the device's help.
+// They change it through DeviceMem, which records the old values for reverse debugging.
+pub trait Peripheral {
+    fn name(&self) -> &str;
+
+    fn range(&self) -> RangeInclusive<u16>;
+
+    fn read(&mut self, addr: u16, mem: &mut DeviceMem) -> i16 {
+        mem.get(addr)
+    }
+
+    fn write(&mut self, addr: u16, value: i16, mem: &mut DeviceMem) {
+        mem.set(addr, value);
+    }
+
+    // called after every instruction, with the time the program had been running for on the
+    // hardware before and after it, see TimingModel
+    fn tick(&mut self, _from: Duration, _to: Duration, _mem: &mut DeviceMem) {}
+}
+
+// data memory as the hooks see it, recording the old value of every word it changes
+pub struct DeviceMem<'a> {
+    mem: &'a mut [i16],
+    overwritten: &'a mut Vec<(u16, i16)>,
+}
+
+impl<'a> DeviceMem<'a> {
+    pub fn new(mem: &'a mut [i16], overwritten: &'a mut Vec<(u16, i16)>) -> Self {
+        DeviceMem { mem, overwritten }
+    }
+
+    pub fn get(&self, addr: u16) -> i16 {
+        self.mem[addr as usize]
+    }
+
+    pub fn set(&mut self, addr: u16, value: i16) {
+        self.overwritten.push((addr, self.mem[addr as usize]));
+        self.mem[addr as usize] = value;
+    }
+}
+
+#[derive(Default)]
+pub struct Bus {
+    devices: Vec<Box<dyn Peripheral>>,
+}
+
+impl Bus {
+    pub fn register(&mut self, device: Box<dyn Peripheral>) -> Result<(), String> {
+        let range = device.range();
+        if let Some(other) = self.devices.iter().find(|other| {
+            let other = other.range();
+            range.start() <= other.end() && other.start() <= range.end()
+        }) {
+            return Err(format!(
+                "{} at 0x{:0>4X}-0x{:0>4X} overlaps {} at 0x{:0>4X}-0x{:0>4X}",
+                device.name(),
+                range.start(),
+                range.end(),
+                other.name(),
+                other.range().start(),
+                other.range().end()
+            ));
+        }
+        self.devices.push(device);
+        Ok(())
+    }
+
+    pub fn get_devices(&self) -> &[Box<dyn Peripheral>] {
+        &self.devices
+    }
+
+    pub fn read(&mut self, addr: u16, mem: &mut DeviceMem) -> i16 {
+        match self.device_at(addr) {
+            Some(device) => device.read(addr, mem),
+            None => mem.get(addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: i16, mem: &mut DeviceMem) {
+        match self.device_at(addr) {
+            Some(device) => device.write(addr, value, mem),
+            None => mem.set(addr, value),
+        }
+    }
+
+    pub fn tick(&mut self, from: Duration, to: Duration, mem: &mut DeviceMem) {
+        for device in &mut self.devices {
+            device.tick(from, to, mem);
+        }
+    }
+
+    fn device_at(&mut self, addr: u16) -> Option<&mut Box<dyn Peripheral>> {
+        // there are only a handful of devices, so a linear search is fine
+        self.devices
+            .iter_mut()
+            .find(|device| device.range().contains(&addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // reads give the address, stores double the value, and ticks count in the first word
+    struct Probe {
+        range: RangeInclusive<u16>,
+    }
+
+    impl Peripheral for Probe {
+        fn name(&self) -> &str {
+            "probe"
+        }
+
+        fn range(&self) -> RangeInclusive<u16> {
+            self.range.clone()
+        }
+
+        fn read(&mut self, addr: u16, _mem: &mut DeviceMem) -> i16 {
+            addr as i16
+        }
+
+        fn write(&mut self, addr: u16, value: i16, mem: &mut DeviceMem) {
+            mem.set(addr, value * 2);
+        }
+
+        fn tick(&mut self, _from: Duration, _to: Duration, mem: &mut DeviceMem) {
+            let start = *self.range.start();
+            mem.set(start, mem.get(start) + 1);
+        }
+    }
+
+    fn probe(range: RangeInclusive<u16>) -> Box<dyn Peripheral> {
+        Box::new(Probe { range })
+    }
+
+    #[test]
+    fn overlapping_devices_are_rejected() {
+        let mut bus = Bus::default();
+        bus.register(probe(0x10..=0x1F)).unwrap();
+        bus.register(probe(0x20..=0x20)).unwrap();
+        assert_eq!(
+            bus.register(probe(0x1F..=0x1F)),
+            Err("probe at 0x001F-0x001F overlaps probe at 0x0010-0x001F".to_string())
+        );
+        assert!(bus.register(probe(0x00..=0x30)).is_err());
+        assert!(bus.register(probe(0x21..=0x30)).is_ok());
+        assert_eq!(bus.get_devices().len(), 3);
+    }
+
+    #[test]
+    fn accesses_go_to_the_device_at_the_address() {
+        let mut bus = Bus::default();
+        bus.register(probe(0x10..=0x1F)).unwrap();
+        let (mut mem, mut overwritten) = (vec![0; 0x40], Vec::new());
+        mem[0x30] = 7;
+        let mut mem = DeviceMem::new(&mut mem, &mut overwritten);
+
+        assert_eq!(bus.read(0x15, &mut mem), 0x15);
+        assert_eq!(bus.read(0x30, &mut mem), 7);
+        bus.write(0x1F, 3, &mut mem);
+        bus.write(0x20, 3, &mut mem);
+        assert_eq!(mem.get(0x1F), 6);
+        assert_eq!(mem.get(0x20), 3);
+        assert_eq!(overwritten, [(0x1F, 0), (0x20, 0)]);
+    }
+
+    #[test]
+    fn every_device_ticks() {
+        let mut bus = Bus::default();
+        bus.register(probe(0x10..=0x1F)).unwrap();
+        bus.register(probe(0x20..=0x2F)).unwrap();
+        let (mut mem, mut overwritten) = (vec![0; 0x40], Vec::new());
+        let mut device_mem = DeviceMem::new(&mut mem, &mut overwritten);
+        for _ in 0..3 {
+            bus.tick(Duration::ZERO, Duration::from_millis(1), &mut device_mem);
+        }
+        assert_eq!((mem[0x10], mem[0x20]), (3, 3));
+        assert_eq!(overwritten.len(), 6);
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+
+use macroquad::prelude::*;
+
+use crate::emu::CpuEmu;
+use crate::graphics::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// where F11 records frames in the window without --record-frames
+const DEFAULT_FRAMES_DIR: &str = "frames";
+
+pub fn save_png(path: &str, buf: &[i16], scale: u32) -> Result<(), String> {
+    // the display in black and white, with every pixel scaled up to `scale` x `scale`
+    let (width, height) = (SCREEN_WIDTH as u32 * scale, SCREEN_HEIGHT as u32 * scale);
+    let words_per_row = SCREEN_WIDTH as usize / 16;
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height {
+        let row = (y / scale) as usize;
+        for x in 0..width {
+            let col = (x / scale) as usize;
+            let word = buf[row * words_per_row + col / 16];
+            let is_pixel_white = (word >> (15 - col % 16)) & 0x01 == 1;
+            pixels.push(if is_pixel_white { 255 } else { 0 });
+        }
+    }
+
+    let file = File::create(path).map_err(|e| format!("could not create {}: {}", path, e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(|e| format!("could not write {}: {}", path, e))
+}
+
+pub fn parse_screenshot(s: &str) -> Result<(u64, String), String> {
+    // COUNT:FILE, e.g. 50000:board.png
+    s.split_once(':')
+        .and_then(|(count, file)| Some((count.parse().ok()?, file.to_string())))
+        .filter(|(_, file)| !file.is_empty())
+        .ok_or(format!(
+            "expected a screenshot like 50000:board.png, found `{}`",
+            s
+        ))
+}
+
+// numbered PNGs in a directory, e.g. to turn into an animation
+pub struct FrameRecorder {
+    dir: String,
+    scale: u32,
+    // frames are numbered on from where the last recording stopped, so none are overwritten
+    next_frame: u32,
+}
+
+impl FrameRecorder {
+    pub fn create(dir: &str, scale: u32) -> Result<Self, String> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("could not create directory {}: {}", dir, e))?;
+        Ok(FrameRecorder {
+            dir: dir.to_string(),
+            scale,
+            next_frame: 0,
+        })
+    }
+
+    pub fn get_dir(&self) -> &str {
+        &self.dir
+    }
+
+    pub fn capture(&mut self, buf: &[i16]) -> Result<(), String> {
+        let path = format!("{}/frame_{:0>5}.png", self.dir, self.next_frame);
+        save_png(&path, buf, self.scale)?;
+        self.next_frame += 1;
+        Ok(())
+    }
+}
+
+// what a headless run saves of the display, by the number of executed instructions
+pub struct Capture {
+    scale: u32,
+    // in the order they are taken
+    screenshots: VecDeque<(u64, String)>,
+    frames: Option<FrameRecorder>,
+    frame_interval: u64,
+    next_frame_count: u64,
+}
+
+impl Capture {
+    pub fn new(mut screenshots: Vec<(u64, String)>, scale: u32) -> Self {
+        screenshots.sort_by_key(|(count, _)| *count);
+        Capture {
+            scale,
+            screenshots: screenshots.into(),
+            frames: None,
+            frame_interval: 1,
+            next_frame_count: 0,
+        }
+    }
+
+    pub fn record_frames(&mut self, frames: FrameRecorder, interval: u64, start: u64) {
+        // a frame every `interval` instructions, starting with the display at `start`
+        self.frames = Some(frames);
+        self.frame_interval = interval.max(1);
+        self.next_frame_count = start;
+    }
+
+    pub fn get_next_count(&self) -> Option<u64> {
+        let screenshot = self.screenshots.front().map(|(count, _)| *count);
+        let frame = self.frames.as_ref().map(|_| self.next_frame_count);
+        screenshot.into_iter().chain(frame).min()
+    }
+
+    pub fn get_missed_screenshots(&self) -> impl Iterator<Item = &(u64, String)> {
+        // the ones the run ended before
+        self.screenshots.iter()
+    }
+
+    pub fn take_due(&mut self, cpu_emulator: &CpuEmu) -> Result<(), String> {
+        let executed = cpu_emulator.get_instructions_executed();
+        let buf = cpu_emulator.get_gfx_buffer();
+        while self
+            .screenshots
+            .front()
+            .is_some_and(|(count, _)| *count <= executed)
+        {
+            let (_, file) = self.screenshots.pop_front().unwrap();
+            save_png(&file, buf, self.scale)?;
+        }
+        if let Some(frames) = &mut self.frames {
+            if self.next_frame_count <= executed {
+                frames.capture(buf)?;
+                self.next_frame_count += self.frame_interval;
+            }
+        }
+        Ok(())
+    }
+}
+
+// F12 saves a screenshot from the window, F11 starts and stops recording a frame sequence
+pub struct WindowCapture {
+    scale: u32,
+    // created when recording first starts, unless --record-frames gave it
+    frames: Option<FrameRecorder>,
+    recording: bool,
+}
+
+impl WindowCapture {
+    pub fn new(frames: Option<FrameRecorder>, scale: u32) -> Self {
+        WindowCapture {
+            scale,
+            recording: frames.is_some(),
+            frames,
+        }
+    }
+
+    pub fn handle_keys(&mut self, cpu_emulator: &CpuEmu) {
+        if is_key_pressed(KeyCode::F12) {
+            let path = format!(
+                "screenshot_{}.png",
+                cpu_emulator.get_instructions_executed()
+            );
+            match save_png(&path, cpu_emulator.get_gfx_buffer(), self.scale) {
+                Ok(()) => println!("saved screenshot to {}", path),
+                Err(e) => eprintln!("error: {}", e),
+            }
+        }
+        if is_key_pressed(KeyCode::F11) {
+            self.recording = !self.recording;
+            if !self.recording {
+                println!("stopped recording frames");
+                return;
+            }
+            if self.frames.is_none() {
+                match FrameRecorder::create(DEFAULT_FRAMES_DIR, self.scale) {
+                    Ok(frames) => self.frames = Some(frames),
+                    Err(e) => {
+                        eprintln!("error: {}", e);
+                        self.recording = false;
+                        return;
+                    }
+                }
+            }
+            println!(
+                "recording frames to {}",
+                self.frames.as_ref().unwrap().get_dir()
+            );
+        }
+    }
+
+    pub fn capture_frame(&mut self, cpu_emulator: &CpuEmu) {
+        // called once for every drawn frame
+        let Some(frames) = self.frames.as_mut().filter(|_| self.recording) else {
+            return;
+        };
+        if let Err(e) = frames.capture(cpu_emulator.get_gfx_buffer()) {
+            eprintln!("error: {}", e);
+            self.recording = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instr_repr::{Operand, Verb};
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("asm_emu_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn frame_files(dir: &std::path::Path) -> Vec<String> {
+        let mut files: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn frames_and_screenshots_are_taken_when_due() {
+        let dir = temp_dir("capture");
+        let frames_dir = dir.join("frames");
+        let screenshot = dir.join("shot.png").to_str().unwrap().to_string();
+        let program = [Verb::Nop, Verb::Jmp(Operand::Imm(0))];
+        let mut cpu_emulator = CpuEmu::new(program.iter().map(Verb::to_bytes).collect());
+
+        let mut capture = Capture::new(vec![(3, screenshot.clone())], 1);
+        let frames = FrameRecorder::create(frames_dir.to_str().unwrap(), 1).unwrap();
+        capture.record_frames(frames, 2, 1);
+        let mut taken = Vec::new();
+        for _ in 0..6 {
+            assert_eq!(capture.get_next_count(), Some(taken.len() as u64 * 2 + 1));
+            cpu_emulator.step().unwrap();
+            capture.take_due(&cpu_emulator).unwrap();
+            let executed = cpu_emulator.get_instructions_executed();
+            if executed % 2 == 1 {
+                taken.push(format!("frame_{:0>5}.png", taken.len()));
+            }
+            assert_eq!(frame_files(&frames_dir), taken, "after {}", executed);
+            assert_eq!(std::path::Path::new(&screenshot).exists(), executed >= 3);
+        }
+        assert_eq!(taken.last().unwrap(), "frame_00002.png");
+        assert_eq!(capture.get_missed_screenshots().count(), 0);
+
+        // a screenshot the run ends before is missed
+        let capture = Capture::new(vec![(10, screenshot.clone()), (8, screenshot)], 1);
+        assert_eq!(capture.get_next_count(), Some(8));
+        assert_eq!(capture.get_missed_screenshots().count(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn screenshots_decode_to_the_display() {
+        let dir = temp_dir("screenshot");
+        let path = dir.join("shot.png");
+        let path = path.to_str().unwrap();
+        let words_per_row = SCREEN_WIDTH as usize / 16;
+        let buf: Vec<i16> = (0..words_per_row * SCREEN_HEIGHT as usize)
+            .map(|i| (i as i16).wrapping_mul(0x1357))
+            .collect();
+        save_png(path, &buf, 2).unwrap();
+
+        let decoder = png::Decoder::new(File::open(path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (320, 240));
+        assert_eq!(info.color_type, png::ColorType::Grayscale);
+        for y in 0..info.height as usize {
+            for x in 0..info.width as usize {
+                let (row, col) = (y / 2, x / 2);
+                let word = buf[row * words_per_row + col / 16];
+                let expected = if (word >> (15 - col % 16)) & 0x01 == 1 {
+                    255
+                } else {
+                    0
+                };
+                assert_eq!(pixels[y * 320 + x], expected, "pixel {},{}", x, y);
+            }
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CodeFileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CodeFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+pub fn parse_code_file(contents: &str) -> Result<Vec<[u8; 3]>, CodeFileError> {
+    // reads the format written by Verb::as_hex_file_line, which is also what
+    // $readmemh accepts: one hex word per line, `_` separators, `//` comments.
+    let mut words = Vec::new();
+
+    for (line_idx, line) in contents.lines().enumerate() {
+        let code = match line.find("//") {
+            Some(comment_start) => &line[..comment_start],
+            None => line,
+        };
+        let digits: String = code.trim().chars().filter(|c| *c != '_').collect();
+        if digits.is_empty() {
+            continue;
+        }
+
+        match u32::from_str_radix(&digits, 16) {
+            Ok(word) if word <= 0xFF_FFFF => {
+                let bytes = word.to_be_bytes();
+                words.push([bytes[1], bytes[2], bytes[3]]);
+            }
+            Ok(_) => {
+                return Err(CodeFileError {
+                    line: line_idx + 1,
+                    message: format!("instruction word `{}` is wider than 24 bits", code.trim()),
+                })
+            }
+            Err(_) => {
+                return Err(CodeFileError {
+                    line: line_idx + 1,
+                    message: format!("invalid hex instruction word `{}`", code.trim()),
+                })
+            }
+        }
+    }
+
+    Ok(words)
+}
+
+pub fn parse_raw_code(bytes: &[u8]) -> Result<Vec<[u8; 3]>, String> {
+    // raw images are just the instruction words back to back, 3 bytes each, so there are no
+    // lines to point at
+    if !bytes.len().is_multiple_of(3) {
+        return Err(format!(
+            "raw image is {} bytes long, which is not a multiple of 3",
+            bytes.len()
+        ));
+    }
+
+    Ok(bytes.chunks(3).map(|c| [c[0], c[1], c[2]]).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_point_at_their_line() {
+        assert_eq!(
+            parse_code_file("00_0000 // nop\n\n1_0000_00\n"),
+            Err(CodeFileError {
+                line: 3,
+                message: "instruction word `1_0000_00` is wider than 24 bits".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_raw_code(&[0; 4]).unwrap_err(),
+            "raw image is 4 bytes long, which is not a multiple of 3"
+        );
+        assert_eq!(parse_raw_code(&[1, 2, 3]), Ok(vec![[1, 2, 3]]));
+    }
+}
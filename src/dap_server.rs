@@ -0,0 +1,762 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use crate::disassembler::symbolize;
+use crate::emu::{CpuEmu, StopReason};
+use crate::fault::EmuFault;
+use crate::hardware::{HardwareProfile, OutOfRange};
+use crate::instr_repr::Verb;
+use crate::program::{assemble_in, Program};
+use crate::tokens::convert_str_to_reg;
+
+// how many instructions to run between checks for new requests, e.g. a pause
+const RUN_CHUNK: u64 = 10_000;
+
+// there is a single CPU, which is reported to the editor as a single thread
+const THREAD_ID: i64 = 1;
+
+const REGISTERS_REF: i64 = 1;
+const LOCATIONS_REF: i64 = 2;
+
+#[derive(Debug, Clone, Copy)]
+enum RunMode {
+    Continue,
+    // run until the call stack is back to this depth, i.e. step over a call
+    StepOver(usize),
+    // run until the call stack is shallower than this depth, i.e. step out of a subroutine
+    StepOut(usize),
+}
+
+struct Session {
+    cpu_emulator: CpuEmu,
+    program: Program,
+    source_path: String,
+    source_name: String,
+}
+
+// Speaks the Debug Adapter Protocol over a pair of streams, usually stdin and stdout.
+// Lines and columns are 1-based, which is the protocol's default.
+pub struct DapServer<W: Write> {
+    output: W,
+    seq: i64,
+    session: Option<Session>,
+    // lines requested by setBreakpoints. They are kept so they can be applied after launch.
+    breakpoint_lines: Vec<i64>,
+    stop_on_entry: bool,
+    configuration_done: bool,
+    run_mode: Option<RunMode>,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(output: W) -> Self {
+        DapServer {
+            output,
+            seq: 1,
+            session: None,
+            breakpoint_lines: Vec::new(),
+            stop_on_entry: false,
+            configuration_done: false,
+            run_mode: None,
+        }
+    }
+
+    pub fn serve(&mut self, input: impl Read + Send + 'static) -> io::Result<()> {
+        // requests are read on another thread, so that a running program can be paused
+        let requests = spawn_reader(input);
+
+        loop {
+            let request = if self.run_mode.is_some() {
+                match requests.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            if let Some(request) = request {
+                if !self.handle_request(&request)? {
+                    return Ok(());
+                }
+            }
+            if let Some(mode) = self.run_mode {
+                self.run_chunk(mode)?;
+            }
+        }
+    }
+
+    fn handle_request(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or("");
+        let args = &request["arguments"];
+
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsEvaluateForHovers": true,
+                "supportsStepBack": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "configurationDone" => {
+                self.configuration_done = true;
+                Ok(Value::Null)
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "cpu" }] })),
+            "stackTrace" => self.with_session(Self::stack_trace),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REF, "expensive": false },
+                { "name": "Locations", "variablesReference": LOCATIONS_REF, "expensive": false },
+            ] })),
+            "variables" => {
+                let reference = args["variablesReference"].as_i64().unwrap_or(0);
+                self.with_session(|session| variables(session, reference))
+            }
+            "evaluate" => {
+                let expression = args["expression"].as_str().unwrap_or("");
+                self.with_session(|session| evaluate(session, expression))
+            }
+            "continue" => self
+                .resume(RunMode::Continue)
+                .map(|_| json!({ "allThreadsContinued": true })),
+            "next" => self.next(),
+            "stepIn" => self.step_in(),
+            "stepOut" => self.step_out(),
+            "stepBack" | "reverseContinue" => self.with_session(|_| Ok(Value::Null)),
+            "pause" => {
+                if self.run_mode.take().is_some() {
+                    self.respond(request, Ok(Value::Null))?;
+                    self.send_stopped("pause", None)?;
+                    return Ok(true);
+                }
+                Ok(Value::Null)
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(Value::Null))?;
+                return Ok(false);
+            }
+            _ => Err(format!("unsupported request `{}`", command)),
+        };
+
+        // a launch response must come before the initialized event, and a step
+        // response before the stopped event, so these are sent in that order here
+        self.respond(request, result)?;
+        match command {
+            "launch" if self.session.is_some() => {
+                self.send_event("initialized", Value::Null)?;
+                // some clients finish configuring before they launch
+                self.start()?;
+            }
+            "configurationDone" => self.start()?,
+            "stepIn" | "next" | "stepOut" if self.session.is_some() => self.after_step()?,
+            "stepBack" | "reverseContinue" if self.session.is_some() => {
+                let cpu = &mut self.session.as_mut().unwrap().cpu_emulator;
+                let stop = match command {
+                    "stepBack" => match cpu.reverse_step() {
+                        Ok(()) => StopReason::Condition,
+                        Err(_) => StopReason::StartOfHistory,
+                    },
+                    _ => cpu.reverse_run(),
+                };
+                self.report_stop(Ok(stop))?;
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        // a relative program path, and vars.locations, are looked up in `cwd` when it is given
+        let cwd = Path::new(args["cwd"].as_str().unwrap_or(""));
+        let program_path = args["program"]
+            .as_str()
+            .ok_or("launch needs a `program` argument naming an .asm file")?;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        let profile = match args["profile"].as_str() {
+            Some(name) => HardwareProfile::from_str(name, true)
+                .map_err(|_| format!("unknown hardware profile `{}`", name))?,
+            None => HardwareProfile::default(),
+        };
+
+        let (_, program) = match assemble_in(cwd, program_path, profile) {
+            Ok(res) => res,
+            Err(e) => {
+                // the rendered errors are easier to read in the debug console than in a popup
+                self.send_output("stderr", &format!("{}\n", e))
+                    .map_err(|e| e.to_string())?;
+                return Err(format!("could not assemble {}", program_path));
+            }
+        };
+
+        let mut cpu_emulator = CpuEmu::new(program.code.clone());
+        cpu_emulator.set_profile(profile, OutOfRange::Fault);
+        cpu_emulator.capture_output();
+        cpu_emulator.enable_history();
+        let source_path = std::fs::canonicalize(cwd.join(program_path))
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or(program_path.to_string());
+        let source_name = Path::new(program_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or(program_path.to_string());
+        self.session = Some(Session {
+            cpu_emulator,
+            program,
+            source_path,
+            source_name,
+        });
+        self.apply_breakpoints();
+        Ok(Value::Null)
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if self.session.is_none() || !self.configuration_done {
+            return Ok(());
+        }
+        if self.stop_on_entry {
+            self.send_stopped("entry", None)
+        } else {
+            self.run_mode = Some(RunMode::Continue);
+            Ok(())
+        }
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        self.breakpoint_lines = args["breakpoints"]
+            .as_array()
+            .map(|bps| bps.iter().filter_map(|bp| bp["line"].as_i64()).collect())
+            .unwrap_or_default();
+        let addrs = self.apply_breakpoints();
+
+        let breakpoints: Vec<Value> = self
+            .breakpoint_lines
+            .iter()
+            .zip(addrs)
+            .map(|(line, addr)| match (addr, &self.session) {
+                (Some(addr), Some(session)) => json!({
+                    "verified": true,
+                    "line": session.program.spans[addr as usize].line,
+                }),
+                _ => json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "no instruction at or after this line",
+                }),
+            })
+            .collect();
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn apply_breakpoints(&mut self) -> Vec<Option<u16>> {
+        // returns the instruction address each requested line was placed at
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => return vec![None; self.breakpoint_lines.len()],
+        };
+        let cpu = &mut session.cpu_emulator;
+        let old: Vec<u16> = cpu.get_breakpoints().iter().copied().collect();
+        for addr in old {
+            cpu.remove_breakpoint(addr);
+        }
+
+        let mut addrs = Vec::new();
+        for line in &self.breakpoint_lines {
+            let addr = line_to_addr(&session.program, *line);
+            if let Some(addr) = addr {
+                cpu.add_breakpoint(addr);
+            }
+            addrs.push(addr);
+        }
+        addrs
+    }
+
+    fn with_session<F>(&self, f: F) -> Result<Value, String>
+    where
+        F: FnOnce(&Session) -> Result<Value, String>,
+    {
+        match &self.session {
+            Some(session) => f(session),
+            None => Err("no program has been launched".to_string()),
+        }
+    }
+
+    fn stack_trace(session: &Session) -> Result<Value, String> {
+        let cpu = &session.cpu_emulator;
+        let call_stack = cpu.get_call_stack();
+        let mut frames = Vec::new();
+        let mut pc = cpu.get_ip();
+        for depth in (0..=call_stack.len()).rev() {
+            let mut frame = json!({
+                "id": depth,
+                "name": symbolize(pc, &session.program.label_map),
+                "line": 0,
+                "column": 0,
+            });
+            if let Some(span) = session.program.spans.get(pc as usize) {
+                frame["source"] =
+                    json!({ "name": session.source_name, "path": session.source_path });
+                frame["line"] = json!(span.line);
+                frame["column"] = json!(span.col + 1);
+            }
+            frames.push(frame);
+            if depth > 0 {
+                pc = call_stack[depth - 1].call_site;
+            }
+        }
+        Ok(json!({ "stackFrames": frames, "totalFrames": call_stack.len() + 1 }))
+    }
+
+    fn resume(&mut self, mode: RunMode) -> Result<(), String> {
+        if self.session.is_none() {
+            return Err("no program has been launched".to_string());
+        }
+        self.run_mode = Some(mode);
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Value, String> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or("no program has been launched")?;
+        let cpu = &session.cpu_emulator;
+        let word = cpu.get_instrs().get(cpu.get_ip() as usize);
+        if matches!(word.map(|w| Verb::from_bytes(*w)), Some(Ok(Verb::Call(_)))) {
+            let depth = cpu.get_call_stack().len();
+            self.resume(RunMode::StepOver(depth))?;
+        }
+        Ok(Value::Null)
+    }
+
+    fn step_in(&mut self) -> Result<Value, String> {
+        if self.session.is_none() {
+            return Err("no program has been launched".to_string());
+        }
+        Ok(Value::Null)
+    }
+
+    fn step_out(&mut self) -> Result<Value, String> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or("no program has been launched")?;
+        let depth = session.cpu_emulator.get_call_stack().len();
+        if depth == 0 {
+            return Err("not inside a subroutine".to_string());
+        }
+        self.resume(RunMode::StepOut(depth))?;
+        Ok(Value::Null)
+    }
+
+    fn after_step(&mut self) -> io::Result<()> {
+        // a next over a call or a step out has been started as a run, everything else
+        // is a single instruction
+        if self.run_mode.is_some() {
+            return Ok(());
+        }
+        let cpu = &mut self.session.as_mut().unwrap().cpu_emulator;
+        let stop = match cpu.step() {
+            Ok(event) if event.halted => Ok(StopReason::Halted),
+            Ok(_) => Ok(StopReason::Condition),
+            Err(fault) => Err(fault),
+        };
+        self.report_stop(stop)
+    }
+
+    fn run_chunk(&mut self, mode: RunMode) -> io::Result<()> {
+        let cpu = &mut self.session.as_mut().unwrap().cpu_emulator;
+        let stop = match mode {
+            RunMode::Continue => cpu.run(RUN_CHUNK),
+            RunMode::StepOver(depth) => {
+                cpu.run_until(RUN_CHUNK, |cpu, _| cpu.get_call_stack().len() <= depth)
+            }
+            RunMode::StepOut(depth) => {
+                cpu.run_until(RUN_CHUNK, |cpu, _| cpu.get_call_stack().len() < depth)
+            }
+        };
+        if let Ok(StopReason::BudgetExhausted) = stop {
+            return self.flush_program_output();
+        }
+        self.run_mode = None;
+        self.report_stop(stop)
+    }
+
+    fn report_stop(&mut self, stop: Result<StopReason, EmuFault>) -> io::Result<()> {
+        self.flush_program_output()?;
+        match stop {
+            Ok(StopReason::Breakpoint(_)) => self.send_stopped("breakpoint", None),
+            Ok(StopReason::Watchpoint { .. }) => self.send_stopped("data breakpoint", None),
+            Ok(StopReason::Condition) | Ok(StopReason::BudgetExhausted) => {
+                self.send_stopped("step", None)
+            }
+            Ok(StopReason::StartOfHistory) => {
+                self.send_stopped("step", Some("reached the start of the recorded history"))
+            }
+            Ok(StopReason::Halted) => {
+                self.send_event("exited", json!({ "exitCode": 0 }))?;
+                self.send_event("terminated", Value::Null)
+            }
+            Err(fault) => {
+                self.send_output("stderr", &format!("fault: {}\n", fault))?;
+                self.send_stopped("exception", Some(&fault.to_string()))
+            }
+        }
+    }
+
+    fn flush_program_output(&mut self) -> io::Result<()> {
+        let lines = self.session.as_mut().unwrap().cpu_emulator.take_output();
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let mut text = lines.join("\n");
+        text.push('\n');
+        self.send_output("stdout", &text)
+    }
+
+    fn send_stopped(&mut self, reason: &str, description: Option<&str>) -> io::Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+            body["text"] = json!(description);
+        }
+        self.send_event("stopped", body)
+    }
+
+    fn send_output(&mut self, category: &str, text: &str) -> io::Result<()> {
+        self.send_event("output", json!({ "category": category, "output": text }))
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let text = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            text.len(),
+            text
+        )?;
+        self.output.flush()
+    }
+}
+
+fn line_to_addr(program: &Program, line: i64) -> Option<u16> {
+    // a breakpoint on a line without an instruction, like a label or a comment,
+    // moves down to the next instruction
+    let line = usize::try_from(line).ok()?;
+    program
+        .spans
+        .iter()
+        .enumerate()
+        .filter(|(_, span)| span.line >= line)
+        .min_by_key(|(addr, span)| (span.line, *addr))
+        .map(|(addr, _)| addr as u16)
+}
+
+fn variables(session: &Session, reference: i64) -> Result<Value, String> {
+    let cpu = &session.cpu_emulator;
+    let mut vars = Vec::new();
+    match reference {
+        REGISTERS_REF => {
+            for (id, value) in cpu.get_regs().iter().enumerate() {
+                vars.push(variable(&format!("R{}", id), *value));
+            }
+            vars.push(json!({
+                "name": "IP",
+                "value": format!("0x{:0>4X}  {}", cpu.get_ip(), symbolize(cpu.get_ip(), &session.program.label_map)),
+                "variablesReference": 0,
+            }));
+        }
+        LOCATIONS_REF => {
+            let mut locations: Vec<(&String, &u16)> = session.program.var_loc_map.iter().collect();
+            locations.sort_by_key(|(name, addr)| (**addr, *name));
+            for (name, addr) in locations {
+                let mut var = variable(name, cpu.get_mem()[*addr as usize]);
+                var["evaluateName"] = json!(name);
+                var["type"] = json!(format!("[0x{:0>4X}]", addr));
+                vars.push(var);
+            }
+        }
+        _ => return Err(format!("unknown variables reference {}", reference)),
+    }
+    Ok(json!({ "variables": vars }))
+}
+
+fn variable(name: &str, value: i16) -> Value {
+    json!({
+        "name": name,
+        "value": format_word(value),
+        "variablesReference": 0,
+    })
+}
+
+fn evaluate(session: &Session, expression: &str) -> Result<Value, String> {
+    // supports hovering over a register, a memory location name or a label
+    let cpu = &session.cpu_emulator;
+    let result = if let Some(reg) = convert_str_to_reg(expression) {
+        format_word(cpu.get_regs()[reg.to_id() as usize])
+    } else if let Some(addr) = session.program.var_loc_map.get(expression) {
+        format_word(cpu.get_mem()[*addr as usize])
+    } else if let Some(addr) = session.program.label_map.get(expression) {
+        format!("0x{:0>4X}", addr)
+    } else {
+        return Err(format!("unknown name `{}`", expression));
+    };
+    Ok(json!({ "result": result, "variablesReference": 0 }))
+}
+
+fn format_word(value: i16) -> String {
+    format!("0x{:0>4X} ({})", value as u16, value)
+}
+
+fn spawn_reader(input: impl Read + Send + 'static) -> Receiver<Value> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        while let Some(message) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+fn read_message(input: &mut impl BufRead) -> Option<Value> {
+    // each message is a Content-Length header, a blank line, then that many bytes of JSON
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(len) = line.strip_prefix("Content-Length:") {
+            content_length = len.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = vec![0; content_length?];
+    input.read_exact(&mut body).ok()?;
+    // a message that is not JSON gets an error response instead of ending the session
+    Some(serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::LOCATIONS_FILE_NAME;
+    use std::sync::mpsc::Sender;
+    use std::thread;
+
+    // the two ends of an in-memory stream
+    struct ChannelReader {
+        chunks: Receiver<Vec<u8>>,
+        pending: Vec<u8>,
+    }
+
+    impl Read for ChannelReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pending.is_empty() {
+                match self.chunks.recv() {
+                    Ok(chunk) => self.pending = chunk,
+                    Err(_) => return Ok(0),
+                }
+            }
+            let len = buf.len().min(self.pending.len());
+            buf[..len].copy_from_slice(&self.pending[..len]);
+            self.pending.drain(..len);
+            Ok(len)
+        }
+    }
+
+    struct ChannelWriter(Sender<Vec<u8>>);
+
+    impl Write for ChannelWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0
+                .send(buf.to_vec())
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn channel() -> (ChannelWriter, ChannelReader) {
+        let (sender, receiver) = mpsc::channel();
+        let reader = ChannelReader {
+            chunks: receiver,
+            pending: Vec::new(),
+        };
+        (ChannelWriter(sender), reader)
+    }
+
+    // the editor's side of the session
+    struct Client {
+        requests: ChannelWriter,
+        messages: BufReader<ChannelReader>,
+        seq: i64,
+        // events that arrived while waiting for a response
+        events: Vec<Value>,
+    }
+
+    impl Client {
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            self.seq += 1;
+            let text = json!({
+                "seq": self.seq,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            })
+            .to_string();
+            write!(
+                self.requests,
+                "Content-Length: {}\r\n\r\n{}",
+                text.len(),
+                text
+            )
+            .unwrap();
+            loop {
+                let message = read_message(&mut self.messages).unwrap();
+                if message["type"] == "response" {
+                    assert_eq!(message["request_seq"], self.seq);
+                    assert_eq!(message["command"], command);
+                    return message;
+                }
+                self.events.push(message);
+            }
+        }
+
+        fn event(&mut self, event: &str) -> Value {
+            if let Some(i) = self.events.iter().position(|e| e["event"] == event) {
+                return self.events.remove(i);
+            }
+            loop {
+                let message = read_message(&mut self.messages).unwrap();
+                if message["event"] == event {
+                    return message;
+                }
+                self.events.push(message);
+            }
+        }
+
+        fn variable(&mut self, reference: i64, name: &str) -> Value {
+            let response = self.request("variables", json!({ "variablesReference": reference }));
+            let vars = response["body"]["variables"].as_array().unwrap();
+            vars.iter().find(|var| var["name"] == name).unwrap()["value"].clone()
+        }
+    }
+
+    #[test]
+    fn scripted_session() {
+        let dir = std::env::temp_dir().join(format!("asm_emu_dap_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(LOCATIONS_FILE_NAME), "LED_ADDR 0x04b4\n").unwrap();
+        let source = "mov r1 5\ncall .add_one\nhalt\n.add_one\n  add r1 1\n  ret\n";
+        std::fs::write(dir.join("prog.asm"), source).unwrap();
+        let cwd = std::env::current_dir().unwrap();
+
+        // the adapter runs on another thread, like an editor would start it as a process
+        let (to_server, server_input) = channel();
+        let (server_output, from_server) = channel();
+        let server = thread::spawn(move || {
+            DapServer::new(server_output).serve(server_input).unwrap();
+        });
+        let mut client = Client {
+            requests: to_server,
+            messages: BufReader::new(from_server),
+            seq: 0,
+            events: Vec::new(),
+        };
+
+        let response = client.request("initialize", json!({ "adapterID": "asm_emu" }));
+        assert_eq!(response["body"]["supportsStepBack"], true);
+        let response = client.request(
+            "launch",
+            json!({ "program": "prog.asm", "cwd": dir.to_str().unwrap() }),
+        );
+        assert_eq!(response["success"], true, "{}", response);
+        client.event("initialized");
+        assert_eq!(std::env::current_dir().unwrap(), cwd);
+
+        // the label's line moves down to the instruction after it
+        let response = client.request("setBreakpoints", json!({ "breakpoints": [{ "line": 4 }] }));
+        assert_eq!(
+            response["body"]["breakpoints"],
+            json!([{ "verified": true, "line": 5 }])
+        );
+        client.request("configurationDone", Value::Null);
+        let stopped = client.event("stopped");
+        assert_eq!(stopped["body"]["reason"], "breakpoint");
+
+        let response = client.request("stackTrace", json!({ "threadId": THREAD_ID }));
+        let frames = &response["body"]["stackFrames"];
+        assert_eq!(response["body"]["totalFrames"], 2);
+        assert_eq!(frames[0]["name"], ".add_one");
+        assert_eq!(frames[0]["line"], 5);
+        assert_eq!(frames[0]["source"]["name"], "prog.asm");
+        assert_eq!(frames[1]["name"], "<entry>+1");
+        assert_eq!(frames[1]["line"], 2);
+
+        let response = client.request("scopes", json!({ "frameId": 0 }));
+        let scopes = &response["body"]["scopes"];
+        assert_eq!(scopes[0]["variablesReference"], REGISTERS_REF);
+        assert_eq!(scopes[1]["variablesReference"], LOCATIONS_REF);
+        assert_eq!(client.variable(REGISTERS_REF, "R1"), "0x0005 (5)");
+        assert_eq!(client.variable(LOCATIONS_REF, "LED_ADDR"), "0x0000 (0)");
+
+        client.request("stepIn", json!({ "threadId": THREAD_ID }));
+        assert_eq!(client.event("stopped")["body"]["reason"], "step");
+        assert_eq!(client.variable(REGISTERS_REF, "R1"), "0x0006 (6)");
+        assert_eq!(client.variable(REGISTERS_REF, "IP"), "0x0004  .add_one+1");
+
+        client.request("continue", json!({ "threadId": THREAD_ID }));
+        assert_eq!(client.event("exited")["body"]["exitCode"], 0);
+        client.event("terminated");
+
+        client.request("disconnect", Value::Null);
+        server.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,595 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use crate::disassembler::{addr_to_label_map, symbolize, with_label_operand};
+use crate::emu::{CpuEmu, StopReason, WatchKind};
+use crate::fault::EmuFault;
+use crate::instr_repr::Verb;
+use crate::label_resolver::resolve_address;
+use crate::seven_segment::describe;
+use crate::tokens::convert_str_to_reg;
+
+// continue, next and finish give control back after this many instructions,
+// so that an infinite loop does not hang the debugger
+const RUN_LIMIT: u64 = 10_000_000;
+
+const HELP: &str = "\
+commands:
+  break LOC          set a breakpoint at a label or instruction address (b)
+  delete LOC         remove a breakpoint (d)
+  watch ADDR[:r|w|rw]  stop when a memory address is read and/or written
+  unwatch ADDR       remove a watchpoint
+  info               list breakpoints and watchpoints
+  step [N]           execute N instructions (s)
+  next               execute one instruction, stepping over calls (n)
+  finish             run until the current subroutine returns
+  continue           run until a breakpoint, watchpoint or halt (c)
+  reverse-step [N]   go back N instructions (rs)
+  reverse-continue   run backwards until a breakpoint or watchpoint (rc)
+  last-write ADDR    go back to the last instruction that wrote ADDR
+  regs               print the registers (r)
+  x/N ADDR           print N words of memory starting at ADDR
+  print NAME         print a register, label, or memory location (p)
+  disasm [LOC [N]]   disassemble N instructions starting at LOC
+  backtrace          print the call stack (bt)
+  quit               exit the debugger (q)
+an empty line repeats the previous command.
+";
+
+pub struct Debugger {
+    cpu_emulator: CpuEmu,
+    label_map: HashMap<String, u16>,
+    var_loc_map: HashMap<String, u16>,
+    addr_labels: HashMap<u16, String>,
+}
+
+impl Debugger {
+    pub fn new(
+        mut cpu_emulator: CpuEmu,
+        label_map: HashMap<String, u16>,
+        var_loc_map: HashMap<String, u16>,
+    ) -> Self {
+        let addr_labels = addr_to_label_map(&label_map);
+        cpu_emulator.enable_history();
+        Debugger {
+            cpu_emulator,
+            label_map,
+            var_loc_map,
+            addr_labels,
+        }
+    }
+
+    pub fn run_repl(&mut self, mut input: impl BufRead, output: &mut impl Write) {
+        let mut last_command = String::new();
+        writeln!(output, "{}", self.location_line()).unwrap();
+
+        loop {
+            write!(output, "(asmdbg) ").unwrap();
+            output.flush().unwrap();
+
+            let mut line = String::new();
+            if input.read_line(&mut line).unwrap_or(0) == 0 {
+                // end of input
+                break;
+            }
+            let command = match line.trim() {
+                "" => last_command.clone(),
+                command => command.to_string(),
+            };
+            if command.is_empty() {
+                continue;
+            }
+            if command == "quit" || command == "q" {
+                break;
+            }
+            last_command = command.clone();
+
+            match self.execute(&command) {
+                Ok(text) => write!(output, "{}", text).unwrap(),
+                Err(e) => writeln!(output, "error: {}", e).unwrap(),
+            }
+        }
+    }
+
+    pub fn execute(&mut self, command: &str) -> Result<String, String> {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+
+        match name {
+            "help" | "h" => Ok(HELP.to_string()),
+            "break" | "b" => {
+                let addr = self.resolve(single_arg(&args)?)?;
+                self.cpu_emulator.add_breakpoint(addr);
+                Ok(format!(
+                    "breakpoint at {}\n",
+                    self.describe_instr_addr(addr)
+                ))
+            }
+            "delete" | "d" => {
+                let addr = self.resolve(single_arg(&args)?)?;
+                if !self.cpu_emulator.remove_breakpoint(addr) {
+                    return Err(format!("no breakpoint at 0x{:X}", addr));
+                }
+                Ok(format!("deleted breakpoint at 0x{:X}\n", addr))
+            }
+            "watch" => {
+                let (addr, kind) =
+                    parse_watchpoint(single_arg(&args)?, &self.label_map, &self.var_loc_map)?;
+                self.cpu_emulator.add_watchpoint(addr, kind);
+                Ok(format!(
+                    "watchpoint ({:?}) on {}\n",
+                    kind,
+                    self.describe_mem_addr(addr)
+                ))
+            }
+            "unwatch" => {
+                let addr = self.resolve(single_arg(&args)?)?;
+                if !self.cpu_emulator.remove_watchpoint(addr) {
+                    return Err(format!("no watchpoint on 0x{:X}", addr));
+                }
+                Ok(format!("deleted watchpoint on 0x{:X}\n", addr))
+            }
+            "info" => Ok(self.info()),
+            "step" | "s" => {
+                let count = match args.first() {
+                    Some(n) => n.parse().map_err(|_| format!("invalid count `{}`", n))?,
+                    None => 1,
+                };
+                self.step(count)
+            }
+            "next" | "n" => self.next(),
+            "finish" => self.finish(),
+            "continue" | "c" => {
+                let stop = self.cpu_emulator.run(RUN_LIMIT);
+                self.after_run(stop)
+            }
+            "reverse-step" | "rs" => {
+                let count = match args.first() {
+                    Some(n) => n.parse().map_err(|_| format!("invalid count `{}`", n))?,
+                    None => 1,
+                };
+                self.reverse_step(count)
+            }
+            "reverse-continue" | "rc" => {
+                let stop = self.cpu_emulator.reverse_run();
+                self.after_run(Ok(stop))
+            }
+            "last-write" => {
+                let addr = self.resolve(single_arg(&args)?)?;
+                let count = self.cpu_emulator.last_write(addr).ok_or(format!(
+                    "{} was not written in the recorded history",
+                    self.describe_mem_addr(addr)
+                ))?;
+                self.cpu_emulator.rewind_to(count)?;
+                Ok(format!(
+                    "{} was last written by instruction {}\n{}\n",
+                    self.describe_mem_addr(addr),
+                    count + 1,
+                    self.location_line()
+                ))
+            }
+            "regs" | "r" => Ok(self.regs()),
+            "print" | "p" => self.print(single_arg(&args)?),
+            "disasm" => {
+                let start = match args.first() {
+                    Some(loc) => self.resolve(loc)?,
+                    None => self.cpu_emulator.get_ip(),
+                };
+                let count = match args.get(1) {
+                    Some(n) => n.parse().map_err(|_| format!("invalid count `{}`", n))?,
+                    None => 10,
+                };
+                Ok(self.disasm(start, count))
+            }
+            "backtrace" | "bt" => Ok(self.backtrace()),
+            _ if name == "x" || name.starts_with("x/") => {
+                let count = match name.strip_prefix("x/") {
+                    Some(n) => n.parse().map_err(|_| format!("invalid count `{}`", n))?,
+                    None => 8,
+                };
+                let addr = self.resolve(single_arg(&args)?)?;
+                Ok(self.examine(addr, count))
+            }
+            _ => Err(format!("unknown command `{}`, try `help`", name)),
+        }
+    }
+
+    fn resolve(&self, location: &str) -> Result<u16, String> {
+        resolve_address(location, &self.label_map, &self.var_loc_map)
+            .ok_or(format!("unknown location `{}`", location))
+    }
+
+    fn step(&mut self, count: u64) -> Result<String, String> {
+        for _ in 0..count {
+            match self.cpu_emulator.step() {
+                Ok(event) if event.halted => return self.after_run(Ok(StopReason::Halted)),
+                Ok(_) => {}
+                Err(fault) => return self.after_run(Err(fault)),
+            }
+        }
+        Ok(format!("{}\n", self.location_line()))
+    }
+
+    fn reverse_step(&mut self, count: u64) -> Result<String, String> {
+        let executed = self.cpu_emulator.get_instructions_executed();
+        if count == 1 {
+            self.cpu_emulator.reverse_step()?;
+        } else {
+            self.cpu_emulator
+                .rewind_to(executed.saturating_sub(count))?;
+        }
+        Ok(format!("{}\n", self.location_line()))
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let ip = self.cpu_emulator.get_ip();
+        if !matches!(self.instr_at(ip), Some(Verb::Call(_))) {
+            return self.step(1);
+        }
+
+        let depth = self.cpu_emulator.get_call_stack().len();
+        let stop = self
+            .cpu_emulator
+            .run_until(RUN_LIMIT, |cpu, _| cpu.get_call_stack().len() <= depth);
+        self.after_run(stop)
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        let depth = self.cpu_emulator.get_call_stack().len();
+        if depth == 0 {
+            return Err("not inside a subroutine".to_string());
+        }
+        let stop = self
+            .cpu_emulator
+            .run_until(RUN_LIMIT, |cpu, _| cpu.get_call_stack().len() < depth);
+        self.after_run(stop)
+    }
+
+    fn after_run(&self, stop: Result<StopReason, EmuFault>) -> Result<String, String> {
+        let reason = match stop {
+            Ok(StopReason::Breakpoint(addr)) => {
+                format!("breakpoint at {}\n", self.describe_instr_addr(addr))
+            }
+            Ok(StopReason::Watchpoint { addr, access, ip }) => format!(
+                "watchpoint: {} of {} by the instruction at {}\n",
+                if access == WatchKind::Read {
+                    "read"
+                } else {
+                    "write"
+                },
+                self.describe_mem_addr(addr),
+                self.describe_instr_addr(ip)
+            ),
+            Ok(StopReason::Halted) => "program halted\n".to_string(),
+            Ok(StopReason::BudgetExhausted) => format!(
+                "stopped after {} instructions without reaching a breakpoint\n",
+                RUN_LIMIT
+            ),
+            Ok(StopReason::StartOfHistory) => {
+                "reached the start of the recorded history\n".to_string()
+            }
+            Ok(StopReason::Condition) => String::new(),
+            // the location line below shows the faulting instruction
+            Err(fault) => format!("fault: {}\n", fault.message()),
+        };
+        Ok(format!("{}{}\n", reason, self.location_line()))
+    }
+
+    fn info(&self) -> String {
+        let mut res = String::new();
+
+        let mut breakpoints: Vec<_> = self.cpu_emulator.get_breakpoints().iter().collect();
+        breakpoints.sort();
+        res.push_str("breakpoints:\n");
+        for addr in breakpoints {
+            res.push_str(&format!("  {}\n", self.describe_instr_addr(*addr)));
+        }
+
+        let mut watchpoints: Vec<_> = self.cpu_emulator.get_watchpoints().iter().collect();
+        watchpoints.sort_by_key(|(addr, _)| **addr);
+        res.push_str("watchpoints:\n");
+        for (addr, kind) in watchpoints {
+            res.push_str(&format!(
+                "  {} ({:?})\n",
+                self.describe_mem_addr(*addr),
+                kind
+            ));
+        }
+
+        if let Some(start) = self.cpu_emulator.get_history_start() {
+            res.push_str(&format!(
+                "history: instructions {} to {} can be reversed\n",
+                start + 1,
+                self.cpu_emulator.get_instructions_executed()
+            ));
+        }
+        res.push_str("peripherals:\n");
+        for device in self.cpu_emulator.get_peripherals() {
+            let range = device.range();
+            res.push_str(&format!(
+                "  0x{:0>4X}-0x{:0>4X} {}\n",
+                range.start(),
+                range.end(),
+                device.name()
+            ));
+        }
+        res.push_str(&format!(
+            "interrupts: {}, pending 0x{:0>4X}\n",
+            if self.cpu_emulator.has_interrupts_enabled() {
+                "enabled"
+            } else {
+                "disabled"
+            },
+            self.cpu_emulator.get_pending_interrupts() as u16
+        ));
+        res.push_str(&format!(
+            "seven-segment display: {}\n",
+            describe(&self.cpu_emulator.get_seven_segment())
+        ));
+        res.push_str(&format!(
+            "time: {} cycles, {:.6} s on the hardware ({:.0} instructions/s)\n",
+            self.cpu_emulator.get_elapsed_cycles(),
+            self.cpu_emulator.get_hardware_time().as_secs_f64(),
+            self.cpu_emulator.get_timing().instrs_per_second()
+        ));
+        res
+    }
+
+    fn regs(&self) -> String {
+        let mut res = format!(
+            "IP  0x{:0>4X}  {}\n",
+            self.cpu_emulator.get_ip(),
+            symbolize(self.cpu_emulator.get_ip(), &self.label_map)
+        );
+        for (id, value) in self.cpu_emulator.get_regs().iter().enumerate() {
+            res.push_str(&format!(
+                "R{:<2} 0x{:0>4X}  ({})\n",
+                id, *value as u16, value
+            ));
+        }
+        res
+    }
+
+    fn print(&self, name: &str) -> Result<String, String> {
+        if let Some(reg) = convert_str_to_reg(name) {
+            let value = self.cpu_emulator.get_regs()[reg.to_id() as usize];
+            return Ok(format!("{} = 0x{:0>4X} ({})\n", reg, value as u16, value));
+        }
+        let addr = self.resolve(name)?;
+        if name.starts_with('.') {
+            return Ok(format!("{} = 0x{:0>4X}\n", name, addr));
+        }
+        let value = self.cpu_emulator.get_mem()[addr as usize];
+        Ok(format!(
+            "{} = 0x{:0>4X} ({})\n",
+            self.describe_mem_addr(addr),
+            value as u16,
+            value
+        ))
+    }
+
+    fn examine(&self, addr: u16, count: usize) -> String {
+        let mem = self.cpu_emulator.get_mem();
+        let mut res = String::new();
+        for (i, a) in (addr as usize..mem.len()).take(count).enumerate() {
+            if i % 8 == 0 {
+                if i > 0 {
+                    res.push('\n');
+                }
+                res.push_str(&format!("0x{:0>4X}:", a));
+            }
+            res.push_str(&format!(" {:0>4X}", mem[a] as u16));
+        }
+        res.push('\n');
+        res
+    }
+
+    fn disasm(&self, start: u16, count: usize) -> String {
+        let mut res = String::new();
+        let end = (start as usize + count).min(self.cpu_emulator.get_instrs().len());
+        for addr in start as usize..end {
+            let addr = addr as u16;
+            if let Some(label) = self.addr_labels.get(&addr) {
+                res.push_str(&format!("{}\n", label));
+            }
+            let marker = if addr == self.cpu_emulator.get_ip() {
+                "=>"
+            } else {
+                "  "
+            };
+            res.push_str(&format!(
+                "{} 0x{:0>4X}  {}\n",
+                marker,
+                addr,
+                self.instr_text(addr)
+            ));
+        }
+        res
+    }
+
+    fn backtrace(&self) -> String {
+        let call_stack = self.cpu_emulator.get_call_stack();
+        let mut res = String::new();
+        let mut pc = self.cpu_emulator.get_ip();
+        for (n, depth) in (0..=call_stack.len()).rev().enumerate() {
+            res.push_str(&format!(
+                "#{:<2} 0x{:0>4X}  {}\n",
+                n,
+                pc,
+                symbolize(pc, &self.label_map)
+            ));
+            if depth > 0 {
+                pc = call_stack[depth - 1].call_site;
+            }
+        }
+        res
+    }
+
+    fn location_line(&self) -> String {
+        let ip = self.cpu_emulator.get_ip();
+        format!(
+            "=> 0x{:0>4X}  {}:  {}",
+            ip,
+            symbolize(ip, &self.label_map),
+            self.instr_text(ip)
+        )
+    }
+
+    fn instr_at(&self, addr: u16) -> Option<Verb> {
+        let word = self.cpu_emulator.get_instrs().get(addr as usize)?;
+        Verb::from_bytes(*word).ok()
+    }
+
+    fn instr_text(&self, addr: u16) -> String {
+        let word = match self.cpu_emulator.get_instrs().get(addr as usize) {
+            Some(word) => *word,
+            None => return "<past the end of the program>".to_string(),
+        };
+        match Verb::from_bytes(word) {
+            Ok(verb) => with_label_operand(verb, &self.addr_labels)
+                .to_string()
+                .trim_end()
+                .to_string(),
+            Err(e) => format!("<{}>", e),
+        }
+    }
+
+    fn describe_instr_addr(&self, addr: u16) -> String {
+        format!("0x{:X} ({})", addr, symbolize(addr, &self.label_map))
+    }
+
+    fn describe_mem_addr(&self, addr: u16) -> String {
+        let mut names: Vec<_> = self
+            .var_loc_map
+            .iter()
+            .filter(|(_, a)| **a == addr)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort();
+        match names.first() {
+            Some(name) => format!("{} [0x{:0>4X}]", name, addr),
+            None => format!("[0x{:0>4X}]", addr),
+        }
+    }
+}
+
+pub fn parse_watchpoint(
+    s: &str,
+    label_map: &HashMap<String, u16>,
+    var_loc_map: &HashMap<String, u16>,
+) -> Result<(u16, WatchKind), String> {
+    // ADDR, ADDR:r, ADDR:w or ADDR:rw
+    let (location, kind) = match s.rsplit_once(':') {
+        Some((location, "r")) => (location, WatchKind::Read),
+        Some((location, "w")) => (location, WatchKind::Write),
+        Some((location, "rw")) => (location, WatchKind::ReadWrite),
+        Some((_, kind)) => return Err(format!("unknown watchpoint kind `{}`", kind)),
+        None => (s, WatchKind::ReadWrite),
+    };
+    match resolve_address(location, label_map, var_loc_map) {
+        Some(addr) => Ok((addr, kind)),
+        None => Err(format!("unknown watchpoint location `{}`", location)),
+    }
+}
+
+fn single_arg<'a>(args: &[&'a str]) -> Result<&'a str, String> {
+    match args {
+        [arg] => Ok(arg),
+        [] => Err("missing argument".to_string()),
+        _ => Err("too many arguments".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::label_resolver::resolve_labels;
+    use crate::tokens::get_tokens;
+
+    const PROGRAM: &str = "\
+mov r1 5
+call .double
+mov [RESULT] r1
+halt
+.double
+  add r1 r1
+  ret
+";
+
+    fn session(script: &str) -> Vec<String> {
+        // the output of each command, after the location line the debugger starts with
+        let var_loc_map = HashMap::from([("RESULT".to_string(), 0x4C0)]);
+        let ((mut verbs, label_map, spans), errors) = get_tokens(PROGRAM.to_string(), &var_loc_map);
+        assert!(errors.is_empty());
+        resolve_labels(&mut verbs, &label_map, &spans).unwrap();
+        let cpu_emulator = CpuEmu::new(verbs.iter().map(Verb::to_bytes).collect());
+        let mut debugger = Debugger::new(cpu_emulator, label_map, var_loc_map);
+
+        let mut output = Vec::new();
+        debugger.run_repl(script.as_bytes(), &mut output);
+        let output = String::from_utf8(output).unwrap();
+        let mut outputs: Vec<String> = output.split("(asmdbg) ").map(String::from).collect();
+        assert_eq!(outputs.remove(0), "=> 0x0000  <entry>:  mov R1 0x5\n");
+        outputs
+    }
+
+    #[test]
+    fn stepping_and_inspecting() {
+        let outputs = session(
+            "break .double\ncontinue\nbacktrace\nstep\nprint r1\nfinish\nnext\nx/2 RESULT\n\
+             print RESULT\nprint .double\nregs\n",
+        );
+        assert_eq!(outputs[0], "breakpoint at 0x4 (.double)\n");
+        assert_eq!(
+            outputs[1],
+            "breakpoint at 0x4 (.double)\n=> 0x0004  .double:  add R1 R1\n"
+        );
+        assert_eq!(outputs[2], "#0  0x0004  .double\n#1  0x0001  <entry>+1\n");
+        assert_eq!(outputs[3], "=> 0x0005  .double+1:  ret\n");
+        assert_eq!(outputs[4], "R1 = 0x000A (10)\n");
+        assert_eq!(outputs[5], "=> 0x0002  <entry>+2:  mov [0x4C0] R1\n");
+        assert_eq!(outputs[6], "=> 0x0003  <entry>+3:  halt\n");
+        assert_eq!(outputs[7], "0x04C0: 000A 0000\n");
+        assert_eq!(outputs[8], "RESULT [0x04C0] = 0x000A (10)\n");
+        assert_eq!(outputs[9], ".double = 0x0004\n");
+        assert!(
+            outputs[10].starts_with("IP  0x0003  <entry>+3\nR0  0x0000  (0)\nR1  0x000A  (10)\n")
+        );
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        let outputs = session("next\nnext\n\nnext\nfinish\n");
+        assert_eq!(outputs[0], "=> 0x0001  <entry>+1:  call .double\n");
+        assert_eq!(outputs[1], "=> 0x0002  <entry>+2:  mov [0x4C0] R1\n");
+        // an empty line repeats the command
+        assert_eq!(outputs[2], "=> 0x0003  <entry>+3:  halt\n");
+        assert_eq!(outputs[3], "program halted\n=> 0x0003  <entry>+3:  halt\n");
+        assert_eq!(outputs[4], "error: not inside a subroutine\n");
+    }
+
+    #[test]
+    fn running_backwards() {
+        let outputs = session(
+            "break .double\ncontinue\ncontinue\nlast-write RESULT\nx/1 RESULT\nreverse-step\n\
+             reverse-continue\nreverse-step 2\nreverse-step\ncontinue\n",
+        );
+        assert_eq!(outputs[2], "program halted\n=> 0x0003  <entry>+3:  halt\n");
+        assert_eq!(
+            outputs[3],
+            "RESULT [0x04C0] was last written by instruction 5\n\
+             => 0x0002  <entry>+2:  mov [0x4C0] R1\n"
+        );
+        assert_eq!(outputs[4], "0x04C0: 0000\n");
+        assert_eq!(outputs[5], "=> 0x0005  .double+1:  ret\n");
+        assert_eq!(
+            outputs[6],
+            "breakpoint at 0x4 (.double)\n=> 0x0004  .double:  add R1 R1\n"
+        );
+        assert_eq!(outputs[7], "=> 0x0000  <entry>:  mov R1 0x5\n");
+        assert_eq!(outputs[8], "error: no more history to go back through\n");
+        // going forward again stops at the same breakpoint
+        assert_eq!(outputs[9], outputs[6]);
+    }
+}
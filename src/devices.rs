@@ -0,0 +1,348 @@
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::bus::{DeviceMem, Peripheral};
+use crate::uart::UartPort;
+
+// the basys3's I/O, at the addresses vars.locations gives them
+pub const VGA_RANGE: RangeInclusive<u16> = 0x0000..=0x04AF;
+pub const SWITCHES_ADDR: u16 = 0x04B0;
+pub const BUTTONS_ADDR: u16 = 0x04B1;
+pub const SEGMENT_LO_ADDR: u16 = 0x04B2;
+pub const SEGMENT_HI_ADDR: u16 = 0x04B3;
+pub const LEDS_ADDR: u16 = 0x04B4;
+pub const TIMER_TICKS_ADDR: u16 = 0x04B5;
+pub const TIMER_COUNTDOWN_ADDR: u16 = 0x04B6;
+pub const TIMER_RELOAD_ADDR: u16 = 0x04B7;
+pub const INT_VECTOR_ADDR: u16 = 0x04B8;
+pub const INT_CAUSE_ADDR: u16 = 0x04B9;
+pub const RNG_ADDR: u16 = 0x04BA;
+pub const UART_TX_ADDR: u16 = 0x04BB;
+pub const UART_RX_ADDR: u16 = 0x04BC;
+pub const UART_STATUS_ADDR: u16 = 0x04BD;
+
+// bits of INT_CAUSE_ADDR, one per interrupt source
+pub const BUTTON_INTERRUPT: i16 = 0x0001;
+pub const TIMER_INTERRUPT: i16 = 0x0002;
+pub const UART_INTERRUPT: i16 = 0x0004;
+
+// bits of UART_STATUS_ADDR
+pub const UART_RX_FULL: i16 = 0x0001;
+pub const UART_TX_READY: i16 = 0x0002;
+
+pub fn basys3_devices(uart: &Rc<RefCell<UartPort>>) -> Vec<Box<dyn Peripheral>> {
+    vec![
+        Box::new(Vga),
+        Box::new(Switches),
+        Box::new(Buttons),
+        Box::new(SevenSegment),
+        Box::new(Leds),
+        Box::new(Timer),
+        Box::new(InterruptController),
+        Box::new(Rng),
+        Box::new(Uart {
+            port: Rc::clone(uart),
+        }),
+    ]
+}
+
+// The frame buffer: 160x120 pixels, 16 per word with the leftmost in the top bit, 1 is white.
+// vga.v scans it out of data memory directly, so loads and stores are plain memory accesses.
+pub struct Vga;
+
+impl Peripheral for Vga {
+    fn name(&self) -> &str {
+        "vga"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        VGA_RANGE
+    }
+}
+
+// Inputs, set by the window or an input script. cpu_unit.v reads the pins for these addresses,
+// so a store by the program goes nowhere.
+pub struct Switches;
+
+impl Peripheral for Switches {
+    fn name(&self) -> &str {
+        "switches"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        SWITCHES_ADDR..=SWITCHES_ADDR
+    }
+
+    fn write(&mut self, _addr: u16, _value: i16, _mem: &mut DeviceMem) {}
+}
+
+// bits 0-4 are btnC, btnR, btnL, btnD and btnU, mapped to the S, D, A, X and W keys
+pub struct Buttons;
+
+impl Peripheral for Buttons {
+    fn name(&self) -> &str {
+        "buttons"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        BUTTONS_ADDR..=BUTTONS_ADDR
+    }
+
+    fn write(&mut self, _addr: u16, _value: i16, _mem: &mut DeviceMem) {}
+}
+
+// see seven_segment.rs for how the two words are shown
+pub struct SevenSegment;
+
+impl Peripheral for SevenSegment {
+    fn name(&self) -> &str {
+        "seven-segment display"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        SEGMENT_LO_ADDR..=SEGMENT_HI_ADDR
+    }
+}
+
+pub struct Leds;
+
+impl Peripheral for Leds {
+    fn name(&self) -> &str {
+        "leds"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        LEDS_ADDR..=LEDS_ADDR
+    }
+}
+
+// A millisecond timer, counting hardware time as the timing model gives it:
+//   TICKS      milliseconds since the program started, wrapping at 65536. Stores are ignored.
+//   COUNTDOWN  counts down to 0 once a millisecond after a store, unsigned
+//   RELOAD     if not 0, COUNTDOWN starts over from this value when it reaches 0
+// Reaching 0 raises TIMER_INTERRUPT.
+pub struct Timer;
+
+impl Peripheral for Timer {
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        TIMER_TICKS_ADDR..=TIMER_RELOAD_ADDR
+    }
+
+    fn write(&mut self, addr: u16, value: i16, mem: &mut DeviceMem) {
+        if addr != TIMER_TICKS_ADDR {
+            mem.set(addr, value);
+        }
+    }
+
+    fn tick(&mut self, from: Duration, to: Duration, mem: &mut DeviceMem) {
+        let ticks = to.as_millis() - from.as_millis();
+        if ticks == 0 {
+            return;
+        }
+        mem.set(TIMER_TICKS_ADDR, to.as_millis() as u16 as i16);
+        let countdown = mem.get(TIMER_COUNTDOWN_ADDR) as u16 as u128;
+        if countdown == 0 {
+            return;
+        }
+        let reload = mem.get(TIMER_RELOAD_ADDR) as u16 as u128;
+        let countdown = if ticks < countdown {
+            countdown - ticks
+        } else {
+            InterruptController::raise(mem, TIMER_INTERRUPT);
+            if reload == 0 {
+                0
+            } else {
+                reload - (ticks - countdown) % reload
+            }
+        };
+        mem.set(TIMER_COUNTDOWN_ADDR, countdown as u16 as i16);
+    }
+}
+
+// Where interrupts go, see CpuEmu::step:
+//   VECTOR  the instruction address of the handler
+//   CAUSE   the interrupts that are pending, one bit per source. Storing a value clears the bits
+//           that are set in it, so a handler can clear exactly the causes it read.
+pub struct InterruptController;
+
+impl InterruptController {
+    pub fn raise(mem: &mut DeviceMem, cause: i16) {
+        // marks an interrupt as pending, for the devices and the inputs that cause them
+        let pending = mem.get(INT_CAUSE_ADDR);
+        if pending & cause != cause {
+            mem.set(INT_CAUSE_ADDR, pending | cause);
+        }
+    }
+}
+
+impl Peripheral for InterruptController {
+    fn name(&self) -> &str {
+        "interrupt controller"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        INT_VECTOR_ADDR..=INT_CAUSE_ADDR
+    }
+
+    fn write(&mut self, addr: u16, value: i16, mem: &mut DeviceMem) {
+        let value = match addr {
+            INT_CAUSE_ADDR => mem.get(addr) & !value,
+            _ => value,
+        };
+        mem.set(addr, value);
+    }
+}
+
+// A xorshift generator with its 16 bit state in the data word, which goes through all the
+// values but 0 before repeating. Each load steps it and gives the new state, a store seeds it.
+// A state of 0 would stay 0, so it stands for DEFAULT_SEED instead.
+pub struct Rng;
+
+// also the seed of headless runs, the debuggers and assembly tests, which all start from it
+pub const DEFAULT_SEED: u16 = 0xACE1;
+
+impl Peripheral for Rng {
+    fn name(&self) -> &str {
+        "rng"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        RNG_ADDR..=RNG_ADDR
+    }
+
+    fn read(&mut self, addr: u16, mem: &mut DeviceMem) -> i16 {
+        let mut state = match mem.get(addr) as u16 {
+            0 => DEFAULT_SEED,
+            state => state,
+        };
+        state ^= state << 7;
+        state ^= state >> 9;
+        state ^= state << 8;
+        mem.set(addr, state as i16);
+        state as i16
+    }
+}
+
+// The basys3's USB-UART, as the program sees it:
+//   TX      a store sends its low byte
+//   RX      the last byte received. A load takes it, clearing UART_RX_FULL.
+//   STATUS  UART_RX_FULL while there is a byte to take, and UART_TX_READY, which is always set
+//           since sending takes no time in the emulator
+// CpuEmu puts received bytes into RX, see CpuEmu::send_uart_input.
+pub struct Uart {
+    port: Rc<RefCell<UartPort>>,
+}
+
+impl Peripheral for Uart {
+    fn name(&self) -> &str {
+        "uart"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        UART_TX_ADDR..=UART_STATUS_ADDR
+    }
+
+    fn read(&mut self, addr: u16, mem: &mut DeviceMem) -> i16 {
+        let status = mem.get(UART_STATUS_ADDR);
+        match addr {
+            UART_RX_ADDR => {
+                if status & UART_RX_FULL != 0 {
+                    mem.set(UART_STATUS_ADDR, status & !UART_RX_FULL);
+                }
+                mem.get(addr)
+            }
+            UART_STATUS_ADDR => status | UART_TX_READY,
+            _ => mem.get(addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: i16, mem: &mut DeviceMem) {
+        // RX and STATUS can only be changed by the UART
+        if addr == UART_TX_ADDR {
+            self.port.borrow_mut().output.push(value as u8);
+            mem.set(addr, value);
+        }
+    }
+}
+
+pub fn seed_from_clock() -> i16 {
+    // for the window without --seed, so that every game plays differently
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.subsec_nanos())
+        .unwrap_or(0);
+    (nanos ^ (nanos >> 16)) as u16 as i16
+}
+
+pub fn parse_seed(s: &str) -> Result<i16, String> {
+    // a 16 bit number, e.g. 1234 or 0xBEEF
+    let seed = match s.strip_prefix("0x") {
+        Some(hex_digits) => u16::from_str_radix(hex_digits, 16),
+        None => s.parse::<u16>(),
+    };
+    seed.map(|seed| seed as i16)
+        .map_err(|_| format!("invalid seed `{}`, expected a 16 bit number", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interrupts_are_raised_and_cleared() {
+        let (mut mem, mut overwritten) = (vec![0; 0x10000], Vec::new());
+        let mut mem = DeviceMem::new(&mut mem, &mut overwritten);
+        InterruptController::raise(&mut mem, TIMER_INTERRUPT);
+        InterruptController::raise(&mut mem, TIMER_INTERRUPT);
+        InterruptController::raise(&mut mem, UART_INTERRUPT);
+        assert_eq!(mem.get(INT_CAUSE_ADDR), TIMER_INTERRUPT | UART_INTERRUPT);
+
+        // a store clears the causes set in it
+        InterruptController.write(INT_CAUSE_ADDR, TIMER_INTERRUPT, &mut mem);
+        assert_eq!(mem.get(INT_CAUSE_ADDR), UART_INTERRUPT);
+        InterruptController.write(INT_VECTOR_ADDR, 0x40, &mut mem);
+        assert_eq!(mem.get(INT_VECTOR_ADDR), 0x40);
+        // raising a pending interrupt again changes nothing, so it is not recorded
+        assert_eq!(overwritten.len(), 4);
+    }
+
+    #[test]
+    fn uart_sends_and_takes_bytes() {
+        let port = Rc::new(RefCell::new(UartPort::default()));
+        let mut uart = Uart {
+            port: Rc::clone(&port),
+        };
+        let (mut mem, mut overwritten) = (vec![0; 0x10000], Vec::new());
+        mem[UART_RX_ADDR as usize] = b'x' as i16;
+        mem[UART_STATUS_ADDR as usize] = UART_RX_FULL;
+        let mut mem = DeviceMem::new(&mut mem, &mut overwritten);
+
+        assert_eq!(
+            uart.read(UART_STATUS_ADDR, &mut mem),
+            UART_RX_FULL | UART_TX_READY
+        );
+        assert_eq!(uart.read(UART_RX_ADDR, &mut mem), b'x' as i16);
+        assert_eq!(uart.read(UART_STATUS_ADDR, &mut mem), UART_TX_READY);
+        // the byte stays in RX, only the flag is taken
+        assert_eq!(uart.read(UART_RX_ADDR, &mut mem), b'x' as i16);
+
+        uart.write(UART_TX_ADDR, 0x141, &mut mem);
+        uart.write(UART_RX_ADDR, 5, &mut mem);
+        uart.write(UART_STATUS_ADDR, 5, &mut mem);
+        assert_eq!(port.borrow().output, b"A");
+        assert_eq!(mem.get(UART_RX_ADDR), b'x' as i16);
+        assert_eq!(mem.get(UART_STATUS_ADDR), 0);
+
+        // every change is recorded for reverse debugging
+        assert_eq!(
+            overwritten,
+            [(UART_STATUS_ADDR, UART_RX_FULL), (UART_TX_ADDR, 0)]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use crate::instr_repr::{Operand, Verb};
+
+pub fn disassemble(words: &[[u8; 3]]) -> String {
+    let decoded: Vec<_> = words.iter().map(|w| Verb::from_bytes(*w)).collect();
+
+    // synthesize a label for every jump or call target that lands inside the program
+    let mut label_map: HashMap<u16, String> = HashMap::new();
+    for verb in decoded.iter().flatten() {
+        if let Some(target) = jump_target(verb) {
+            if (target as usize) < words.len() {
+                label_map.insert(target, format!(".L_{:0>4X}", target));
+            }
+        }
+    }
+
+    let mut res = String::new();
+    for (addr, (word, verb)) in words.iter().zip(decoded).enumerate() {
+        if let Some(label) = label_map.get(&(addr as u16)) {
+            res.push_str(&format!("{}\n", label));
+        }
+
+        let text = match verb {
+            Ok(verb) => with_label_operand(verb, &label_map).to_string(),
+            Err(e) => format!("??  ; {}", e),
+        };
+        res.push_str(&format!(
+            "  {:0>4X}:  {:0>2X}_{:0>2X}_{:0>2X}  {}\n",
+            addr,
+            word[0],
+            word[1],
+            word[2],
+            text.trim_end()
+        ));
+    }
+    res
+}
+
+pub fn jump_target(verb: &Verb) -> Option<u16> {
+    match verb {
+        Verb::Jmp(Operand::Imm(target))
+        | Verb::Jz(Operand::Imm(target), _)
+        | Verb::Jnz(Operand::Imm(target), _)
+        | Verb::Call(Operand::Imm(target)) => Some(*target),
+        _ => None,
+    }
+}
+
+pub fn with_label_operand(verb: Verb, label_map: &HashMap<u16, String>) -> Verb {
+    let label = match jump_target(&verb).and_then(|t| label_map.get(&t)) {
+        Some(label) => Operand::Label(label.clone()),
+        None => return verb,
+    };
+    match verb {
+        Verb::Jmp(_) => Verb::Jmp(label),
+        Verb::Jz(_, r) => Verb::Jz(label, r),
+        Verb::Jnz(_, r) => Verb::Jnz(label, r),
+        Verb::Call(_) => Verb::Call(label),
+        _ => verb,
+    }
+}
+
+pub fn addr_to_label_map(label_map: &HashMap<String, u16>) -> HashMap<u16, String> {
+    // when several labels share an address, the alphabetically first one is used
+    let mut res: HashMap<u16, String> = HashMap::new();
+    for (label, addr) in label_map {
+        match res.get(addr) {
+            Some(existing) if existing <= label => {}
+            _ => {
+                res.insert(*addr, label.clone());
+            }
+        }
+    }
+    res
+}
+
+pub fn symbolize(addr: u16, label_map: &HashMap<String, u16>) -> String {
+    // names an instruction address relative to the closest label before it, e.g. `.clear_screen+3`
+    let closest = label_map
+        .iter()
+        .filter(|(_, label_addr)| **label_addr <= addr)
+        .max_by(|(l1, a1), (l2, a2)| a1.cmp(a2).then(l2.cmp(l1)));
+    match closest {
+        Some((label, label_addr)) if *label_addr == addr => label.clone(),
+        Some((label, label_addr)) => format!("{}+{}", label, addr - label_addr),
+        // code before the first label is where execution starts
+        None if addr == 0 => "<entry>".to_string(),
+        None => format!("<entry>+{}", addr),
+    }
+}
@@ -1,207 +1,1359 @@
-use crate::instr_repr::{Operand, Verb};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::bus::{Bus, DeviceMem, Peripheral};
+use crate::devices::{
+    basys3_devices, InterruptController, BUTTONS_ADDR, BUTTON_INTERRUPT, INT_CAUSE_ADDR,
+    INT_VECTOR_ADDR, LEDS_ADDR, SEGMENT_HI_ADDR, SEGMENT_LO_ADDR, SWITCHES_ADDR, UART_INTERRUPT,
+    UART_RX_ADDR, UART_RX_FULL, UART_STATUS_ADDR, VGA_RANGE,
+};
+use crate::fault::EmuFault;
+use crate::hardware::{HardwareProfile, OutOfRange};
+use crate::history::{CallStackChange, DeviceWrite, History, Snapshot, UndoEntry};
+use crate::input_script::InputScript;
+use crate::instr_repr::{Operand, Reg, Verb};
+use crate::machine_state::{program_hash, MachineState};
+use crate::seven_segment::segment_patterns;
+use crate::timing::TimingModel;
+use crate::trace::Tracer;
+use crate::uart::UartPort;
+
+// instructions run_flat_out runs between looking at the clock
+const FLAT_OUT_CHUNK: u64 = 1000;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StepEvent {
+    // address of the instruction that was executed
+    pub ip: u16,
+    pub verb: Verb,
+    pub reg_written: Option<Reg>,
+    pub mem_read: Option<u16>,
+    pub mem_written: Option<u16>,
+    pub jump_taken: bool,
+    pub halted: bool,
+    // the step entered an interrupt handler instead of executing an instruction. `verb` is then
+    // the call to the handler that it amounts to.
+    pub interrupt: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(&self, access: WatchKind) -> bool {
+        *self == WatchKind::ReadWrite || *self == access
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StopReason {
+    Breakpoint(u16),
+    Watchpoint {
+        addr: u16,
+        access: WatchKind,
+        ip: u16,
+    },
+    Halted,
+    BudgetExhausted,
+    // a reverse run went back as far as the recorded history goes
+    StartOfHistory,
+    // the stop condition given to run_until was met
+    Condition,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CallFrame {
+    // address of the call instruction, and of the subroutine it called
+    pub call_site: u16,
+    pub target: u16,
+}
 
 pub struct CpuEmu {
-    instrs: Vec<Verb>,
+    // instruction memory holds encoded 24 bit words, exactly like instr_mem in cpu_unit.v
+    instrs: Vec<[u8; 3]>,
     ip: u16,
     halted: bool,
+    // set by ei and iret, cleared by di and when an interrupt is delivered
+    interrupts_enabled: bool,
     regs: [i16; 16],
     mem: [i16; 65536],
+    instructions_executed: u64,
+    breakpoints: HashSet<u16>,
+    watchpoints: HashMap<u16, WatchKind>,
+    // shadow stack of the calls that have not returned yet, for debuggers
+    call_stack: Vec<CallFrame>,
+    // when set, lines printed by dbg and halt are kept here instead of going to stdout
+    captured_output: Option<Vec<String>>,
+    // what the program sent over the UART since the last newline, while output is captured
+    uart_line: String,
+    // the UART output printed so far does not end with a newline
+    uart_line_open: bool,
+    tracer: Option<Tracer>,
+    // recorded while history is enabled, so that execution can be reversed
+    history: Option<History>,
+    // what the bus gave the instruction being executed, and what it stored, for the trace. A
+    // device can make them differ from what ends up in memory.
+    read_value: Option<i16>,
+    stored_value: Option<i16>,
+    // set while re-executing from a snapshot, so that output is not printed twice
+    replaying: bool,
+    // feeds the switches and buttons at fixed instruction counts, instead of the front-end
+    input_script: Option<InputScript>,
+    timing: TimingModel,
+    // which data addresses exist on the hardware, and what accessing one that does not does
+    profile: HardwareProfile,
+    out_of_range: OutOfRange,
+    // addresses that were already warned about with OutOfRange::Warn
+    warned_addrs: HashSet<u16>,
+    // memory-mapped devices that loads and stores by the program go through
+    bus: Bus,
+    uart: Rc<RefCell<UartPort>>,
+    // old values of the words the current instruction and the devices changed, see DeviceMem
+    device_writes: Vec<(u16, i16)>,
+    // cycles of wall-clock time that run_for has not spent yet, less than one instruction's worth
+    pending_cycles: u64,
 }
 
 impl CpuEmu {
-    pub fn new(instrs: Vec<Verb>) -> Self {
-        CpuEmu {
+    pub fn new(instrs: Vec<[u8; 3]>) -> Self {
+        let mut cpu = CpuEmu {
             instrs,
             ip: 0,
             halted: false,
+            interrupts_enabled: false,
             regs: [0; 16],
             mem: [0; 65536],
+            instructions_executed: 0,
+            breakpoints: HashSet::new(),
+            watchpoints: HashMap::new(),
+            call_stack: Vec::new(),
+            captured_output: None,
+            uart_line: String::new(),
+            uart_line_open: false,
+            tracer: None,
+            history: None,
+            read_value: None,
+            stored_value: None,
+            replaying: false,
+            input_script: None,
+            timing: TimingModel::default(),
+            profile: HardwareProfile::default(),
+            out_of_range: OutOfRange::default(),
+            warned_addrs: HashSet::new(),
+            bus: Bus::default(),
+            uart: Rc::default(),
+            device_writes: Vec::new(),
+            pending_cycles: 0,
+        };
+        for device in basys3_devices(&cpu.uart) {
+            cpu.add_peripheral(device)
+                .expect("the basys3 devices do not overlap");
         }
+        cpu
     }
 
     pub fn get_gfx_buffer(&self) -> &[i16] {
-        return &self.mem.as_slice()[0..1200];
+        &self.mem.as_slice()[*VGA_RANGE.start() as usize..=*VGA_RANGE.end() as usize]
+    }
+
+    pub fn add_peripheral(&mut self, device: Box<dyn Peripheral>) -> Result<(), String> {
+        self.bus.register(device)
+    }
+
+    pub fn get_peripherals(&self) -> &[Box<dyn Peripheral>] {
+        self.bus.get_devices()
     }
 
     pub fn get_led_output(&self) -> i16 {
-        return self.mem[1204];
+        self.mem[LEDS_ADDR as usize]
+    }
+
+    pub fn get_seven_segment(&self) -> [u8; 4] {
+        segment_patterns(
+            self.mem[SEGMENT_LO_ADDR as usize],
+            self.mem[SEGMENT_HI_ADDR as usize],
+        )
     }
 
     pub fn set_switch_states(&mut self, new_states: i16) {
-        self.mem[1200] = new_states;
+        self.mem[SWITCHES_ADDR as usize] = new_states;
+        self.forget_history();
+    }
+
+    pub fn get_switch_states(&self) -> i16 {
+        self.mem[SWITCHES_ADDR as usize]
+    }
+
+    pub fn get_button_states(&self) -> i16 {
+        self.mem[BUTTONS_ADDR as usize]
     }
 
     pub fn set_button_states(&mut self, new_states: i16) {
-        self.mem[1201] = new_states;
-    }
-
-    pub fn run_some_instructions(&mut self) {
-        // runs 416 instructions
-        // the hardware clock runs at 100Mhz, which is stepped down to 100KHz,
-        // and we execute 1 instruction every 8 clock cycles. So 12.5K instructions are run every second.
-        // Since the framerate of the emulator is 60 fps, 12500/60 = 208
-        for _ in 0..208 {
-            let next_instr = self
-                .instrs
-                .get(self.ip as usize)
-                .expect("program execution continued into undefined instructions!");
-            match next_instr {
-                Verb::Mov(op1, op2) => match (op1, op2) {
-                    (Operand::Reg(reg), Operand::Imm(imm)) => {
-                        self.regs[reg.to_id() as usize] = *imm as i16;
-                    }
-                    (Operand::Reg(reg), Operand::MemAtImm(imm)) => {
-                        self.regs[reg.to_id() as usize] = self.mem[*imm as usize];
-                    }
-                    (Operand::MemAtImm(imm), Operand::Reg(reg)) => {
-                        self.mem[*imm as usize] = self.regs[reg.to_id() as usize];
-                    }
-                    (Operand::Reg(reg1), Operand::Reg(reg2)) => {
-                        self.regs[reg1.to_id() as usize] = self.regs[reg2.to_id() as usize];
-                    }
-                    (Operand::Reg(reg1), Operand::MemAtReg(reg2)) => {
-                        self.regs[reg1.to_id() as usize] =
-                            self.mem[self.regs[reg2.to_id() as usize] as u16 as usize];
-                    }
-                    (Operand::MemAtReg(reg1), Operand::Reg(reg2)) => {
-                        self.mem[self.regs[reg1.to_id() as usize] as u16 as usize] =
-                            self.regs[reg2.to_id() as usize];
-                    }
-                    _ => unreachable!(),
-                },
-                Verb::Jmp(imm) => {
-                    self.ip = imm.to_imm().overflowing_sub(1).0;
-                }
-                Verb::Jz(imm, reg) | Verb::Jnz(imm, reg) => {
-                    let imm = imm.to_imm();
-                    let reg = reg.to_reg();
-                    let reg_value = self.regs[reg.to_id() as usize];
-
-                    let jump_taken = match next_instr {
-                        Verb::Jz(..) => reg_value == 0,
-                        Verb::Jnz(..) => reg_value != 0,
-                        _ => unreachable!(),
-                    };
-                    if jump_taken {
-                        self.ip = imm.overflowing_sub(1).0;
+        if new_states & !self.mem[BUTTONS_ADDR as usize] != 0 {
+            self.mem[INT_CAUSE_ADDR as usize] |= BUTTON_INTERRUPT;
+        }
+        self.mem[BUTTONS_ADDR as usize] = new_states;
+        self.forget_history();
+    }
+
+    pub fn get_ip(&self) -> u16 {
+        self.ip
+    }
+
+    pub fn set_ip(&mut self, ip: u16) {
+        self.ip = ip;
+        self.forget_history();
+    }
+
+    pub fn set_reg(&mut self, reg: Reg, value: i16) {
+        self.regs[reg.to_id() as usize] = value;
+        self.forget_history();
+    }
+
+    pub fn set_mem(&mut self, addr: u16, value: i16) {
+        self.mem[addr as usize] = value;
+        self.forget_history();
+    }
+
+    pub fn get_instrs(&self) -> &[[u8; 3]] {
+        &self.instrs
+    }
+
+    pub fn get_regs(&self) -> &[i16; 16] {
+        &self.regs
+    }
+
+    pub fn get_mem(&self) -> &[i16] {
+        self.mem.as_slice()
+    }
+
+    pub fn get_instructions_executed(&self) -> u64 {
+        self.instructions_executed
+    }
+
+    pub fn capture_output(&mut self) {
+        self.captured_output.get_or_insert_with(Vec::new);
+    }
+
+    pub fn take_output(&mut self) -> Vec<String> {
+        self.captured_output
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    pub fn send_uart_input(&mut self, bytes: &[u8]) {
+        // the bytes are received one at a time, each once the program has taken the one before
+        self.uart.borrow_mut().input.extend(bytes);
+    }
+
+    pub fn set_input_script(&mut self, script: InputScript) {
+        self.input_script = Some(script);
+    }
+
+    pub fn has_input_script(&self) -> bool {
+        self.input_script.is_some()
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn flush_trace(&mut self) -> Result<(), String> {
+        // a trace that could not be written is dropped, so its error is only reported once
+        let res = self.tracer.as_mut().map_or(Ok(()), Tracer::flush);
+        if res.is_err() {
+            self.tracer = None;
+        }
+        res
+    }
+
+    fn print_line(&mut self, line: String) {
+        if self.replaying {
+            return;
+        }
+        self.end_uart_line();
+        match &mut self.captured_output {
+            Some(lines) => lines.push(line),
+            None => println!("{}", line),
+        }
+    }
+
+    pub fn end_uart_line(&mut self) {
+        // so that other output does not continue a line the program sent over the UART
+        match &mut self.captured_output {
+            Some(lines) if !self.uart_line.is_empty() => {
+                lines.push(std::mem::take(&mut self.uart_line))
+            }
+            None if self.uart_line_open => println!(),
+            _ => {}
+        }
+        self.uart_line_open = false;
+    }
+
+    fn print_uart(&mut self, bytes: &[u8]) {
+        if self.replaying {
+            return;
+        }
+        match &mut self.captured_output {
+            Some(lines) => {
+                for byte in bytes {
+                    match byte {
+                        b'\n' => lines.push(std::mem::take(&mut self.uart_line)),
+                        _ => self.uart_line.push(*byte as char),
                     }
                 }
+            }
+            None => {
+                let text: String = bytes.iter().map(|byte| *byte as char).collect();
+                print!("{}", text);
+                std::io::stdout().flush().ok();
+                self.uart_line_open = !text.ends_with('\n');
+            }
+        }
+    }
 
-                Verb::Add(op1, op2) => {
-                    let ra = op1.to_reg();
-                    if let Operand::Reg(rb) = op2 {
-                        self.regs[ra.to_id() as usize] = self.regs[ra.to_id() as usize]
-                            .overflowing_add(self.regs[rb.to_id() as usize])
-                            .0;
-                    } else {
-                        self.regs[ra.to_id() as usize] = self.regs[ra.to_id() as usize]
-                            .overflowing_add(op2.to_imm() as i16)
-                            .0;
-                    }
+    fn apply_input_script(&mut self) {
+        // unlike inputs from the window, scripted ones are part of the run, so they are recorded
+        // like device writes and undone with the step instead of dropping the history
+        let Some(script) = &mut self.input_script else {
+            return;
+        };
+        let mut mem = DeviceMem::new(&mut self.mem, &mut self.device_writes);
+        let (old_switches, old_buttons) = (mem.get(SWITCHES_ADDR), mem.get(BUTTONS_ADDR));
+        let (mut switches, mut buttons) = (old_switches, old_buttons);
+        if !script.apply(self.instructions_executed, &mut switches, &mut buttons) {
+            return;
+        }
+        if switches != old_switches {
+            mem.set(SWITCHES_ADDR, switches);
+        }
+        if buttons != old_buttons {
+            mem.set(BUTTONS_ADDR, buttons);
+        }
+        if buttons & !old_buttons != 0 {
+            InterruptController::raise(&mut mem, BUTTON_INTERRUPT);
+        }
+    }
+
+    fn get_script_position(&self) -> usize {
+        self.input_script
+            .as_ref()
+            .map_or(0, InputScript::get_position)
+    }
+
+    fn set_script_position(&mut self, position: usize) {
+        if let Some(script) = &mut self.input_script {
+            script.set_position(position);
+        }
+    }
+
+    fn drop_inputs(&mut self, script_position: usize, uart_received: usize) {
+        // a faulting instruction changes nothing, so the inputs applied before it are taken back
+        // and arrive again if the step is retried, instead of staying without an undo entry
+        for (addr, old) in self.device_writes.drain(..).rev() {
+            self.mem[addr as usize] = old;
+        }
+        self.set_script_position(script_position);
+        self.uart.borrow_mut().set_received_count(uart_received);
+    }
+
+    fn receive_uart_byte(&mut self) {
+        // like scripted inputs, received bytes are recorded with the step, and undoing it puts
+        // the byte back into the input
+        let mut mem = DeviceMem::new(&mut self.mem, &mut self.device_writes);
+        let status = mem.get(UART_STATUS_ADDR);
+        if status & UART_RX_FULL != 0 {
+            return;
+        }
+        let Some(byte) = self.uart.borrow_mut().receive() else {
+            return;
+        };
+        mem.set(UART_RX_ADDR, byte as i16);
+        mem.set(UART_STATUS_ADDR, status | UART_RX_FULL);
+        InterruptController::raise(&mut mem, UART_INTERRUPT);
+    }
+
+    fn fetch(&self, addr: u16) -> Result<Verb, EmuFault> {
+        let word = self
+            .instrs
+            .get(addr as usize)
+            .ok_or(EmuFault::IpOutOfRange { ip: addr })?;
+        Verb::from_bytes(*word).map_err(|error| EmuFault::BadInstruction { ip: addr, error })
+    }
+
+    pub fn set_timing(&mut self, timing: TimingModel) {
+        self.timing = timing;
+        self.pending_cycles = 0;
+    }
+
+    pub fn set_profile(&mut self, profile: HardwareProfile, out_of_range: OutOfRange) {
+        self.profile = profile;
+        self.out_of_range = out_of_range;
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn has_interrupts_enabled(&self) -> bool {
+        self.interrupts_enabled
+    }
+
+    pub fn get_pending_interrupts(&self) -> i16 {
+        self.mem[INT_CAUSE_ADDR as usize]
+    }
+
+    pub fn get_timing(&self) -> TimingModel {
+        self.timing
+    }
+
+    pub fn get_elapsed_cycles(&self) -> u64 {
+        // every instruction takes the same number of stages, see TimingModel
+        self.instructions_executed * self.timing.cycles_per_instr()
+    }
+
+    pub fn get_hardware_time(&self) -> Duration {
+        self.timing.cycles_to_duration(self.get_elapsed_cycles())
+    }
+
+    pub fn run_flat_out(&mut self, budget: Duration) -> Result<(), EmuFault> {
+        // runs as many instructions as fit in `budget` of wall-clock time, ignoring the timing model
+        let start = Instant::now();
+        self.pending_cycles = 0;
+        while !self.halted && start.elapsed() < budget {
+            self.run_instructions(FLAT_OUT_CHUNK)?;
+        }
+        Ok(())
+    }
+
+    pub fn run_for(&mut self, elapsed: Duration) -> Result<(), EmuFault> {
+        // runs as many instructions as the hardware would in `elapsed`. Cycles that are not
+        // enough for a whole instruction are carried over to the next call.
+        let cycles_per_instr = self.timing.cycles_per_instr();
+        self.pending_cycles += self.timing.duration_to_cycles(elapsed);
+        let count = self.pending_cycles / cycles_per_instr;
+        self.pending_cycles %= cycles_per_instr;
+        self.run_instructions(count)
+    }
+
+    fn run_instructions(&mut self, count: u64) -> Result<(), EmuFault> {
+        for _ in 0..count {
+            self.step()?;
+            if self.halted {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn step(&mut self) -> Result<StepEvent, EmuFault> {
+        if self.halted {
+            // the board stops at halt, so nothing runs and no time passes, however often the
+            // window asks for another instruction
+            return Ok(StepEvent {
+                ip: self.ip,
+                verb: Verb::Halt,
+                reg_written: None,
+                mem_read: None,
+                mem_written: None,
+                jump_taken: false,
+                halted: true,
+                interrupt: false,
+            });
+        }
+        let (count, ip) = (self.instructions_executed, self.ip);
+        let interrupts_enabled = self.interrupts_enabled;
+        let old_regs = self.regs;
+        let script_position = self.get_script_position();
+        let uart_received = self.uart.borrow().get_received_count();
+        if matches!(&self.history, Some(history) if history.wants_snapshot(count)) {
+            let snapshot = self.snapshot();
+            self.history.as_mut().unwrap().push_snapshot(snapshot);
+        }
+
+        self.apply_input_script();
+        self.receive_uart_byte();
+
+        self.read_value = None;
+        self.stored_value = None;
+        let call_depth = self.call_stack.len();
+        let popped_frame = self.call_stack.last().copied();
+        let result = if interrupts_enabled && self.get_pending_interrupts() != 0 {
+            self.deliver_interrupt()
+        } else {
+            self.execute()
+        };
+        let event = match result {
+            Ok(event) => event,
+            Err(fault) => {
+                self.drop_inputs(script_position, uart_received);
+                return Err(fault);
+            }
+        };
+        let from = self
+            .timing
+            .cycles_to_duration(count * self.timing.cycles_per_instr());
+        let to = self.get_hardware_time();
+        self.bus.tick(
+            from,
+            to,
+            &mut DeviceMem::new(&mut self.mem, &mut self.device_writes),
+        );
+
+        if let Some(history) = &mut self.history {
+            let call_stack = match self.call_stack.len().cmp(&call_depth) {
+                std::cmp::Ordering::Greater => CallStackChange::Pushed,
+                std::cmp::Ordering::Less => CallStackChange::Popped(popped_frame.unwrap()),
+                std::cmp::Ordering::Equal => CallStackChange::None,
+            };
+            history.push(UndoEntry {
+                count,
+                ip,
+                interrupts_enabled,
+                reg: event
+                    .reg_written
+                    .map(|reg| (reg, old_regs[reg.to_id() as usize])),
+                mem_written: event.mem_written,
+                mem_read: event.mem_read,
+                call_stack,
+                script_position,
+                uart_received,
+            });
+            for (addr, old) in self.device_writes.iter().copied() {
+                history.push_device_write(DeviceWrite { count, addr, old });
+            }
+        }
+        self.device_writes.clear();
+        let uart_output = std::mem::take(&mut self.uart.borrow_mut().output);
+        if !uart_output.is_empty() {
+            self.print_uart(&uart_output);
+        }
+        if let Some(tracer) = &mut self.tracer {
+            // an interrupt can be delivered with the IP past the end of the program
+            let word = self.instrs.get(event.ip as usize).copied();
+            tracer.record(
+                self.instructions_executed,
+                word,
+                &event,
+                &self.regs,
+                (self.read_value, self.stored_value),
+            );
+        }
+        Ok(event)
+    }
+
+    fn execute(&mut self) -> Result<StepEvent, EmuFault> {
+        let next_instr = &self.fetch(self.ip)?;
+        self.check_instr(next_instr)?;
+        self.instructions_executed += 1;
+
+        let mut event = StepEvent {
+            ip: self.ip,
+            verb: next_instr.clone(),
+            reg_written: None,
+            mem_read: None,
+            mem_written: None,
+            jump_taken: false,
+            halted: false,
+            interrupt: false,
+        };
+
+        match next_instr {
+            Verb::Mov(op1, op2) => match (op1, op2) {
+                (Operand::Reg(reg), Operand::Imm(imm)) => {
+                    self.regs[reg.to_id() as usize] = *imm as i16;
+                    event.reg_written = Some(*reg);
                 }
-                Verb::Sub(op1, op2) => {
-                    let ra = op1.to_reg();
-                    if let Operand::Reg(rb) = op2 {
-                        self.regs[ra.to_id() as usize] = self.regs[ra.to_id() as usize]
-                            .overflowing_sub(self.regs[rb.to_id() as usize])
-                            .0;
-                    } else {
-                        self.regs[ra.to_id() as usize] = self.regs[ra.to_id() as usize]
-                            .overflowing_sub(op2.to_imm() as i16)
-                            .0;
-                    }
+                (Operand::Reg(reg), Operand::MemAtImm(imm)) => {
+                    self.regs[reg.to_id() as usize] = self.read_mem(*imm);
+                    event.reg_written = Some(*reg);
+                    event.mem_read = Some(*imm);
                 }
-                Verb::And(op1, op2) => {
-                    let ra = op1.to_reg();
-                    if let Operand::Reg(rb) = op2 {
-                        self.regs[ra.to_id() as usize] &= self.regs[rb.to_id() as usize];
-                    } else {
-                        self.regs[ra.to_id() as usize] &= op2.to_imm() as i16;
-                    }
+                (Operand::MemAtImm(imm), Operand::Reg(reg)) => {
+                    self.write_mem(*imm, self.regs[reg.to_id() as usize]);
+                    event.mem_written = Some(*imm);
                 }
-                Verb::Or(op1, op2) => {
-                    let ra = op1.to_reg();
-                    if let Operand::Reg(rb) = op2 {
-                        self.regs[ra.to_id() as usize] |= self.regs[rb.to_id() as usize];
-                    } else {
-                        self.regs[ra.to_id() as usize] |= op2.to_imm() as i16;
-                    }
+                (Operand::Reg(reg1), Operand::Reg(reg2)) => {
+                    self.regs[reg1.to_id() as usize] = self.regs[reg2.to_id() as usize];
+                    event.reg_written = Some(*reg1);
                 }
-                Verb::Not(ra) => {
-                    let ra = ra.to_reg();
-                    self.regs[ra.to_id() as usize] = !self.regs[ra.to_id() as usize];
+                (Operand::Reg(reg1), Operand::MemAtReg(reg2)) => {
+                    let addr = self.regs[reg2.to_id() as usize] as u16;
+                    self.regs[reg1.to_id() as usize] = self.read_mem(addr);
+                    event.reg_written = Some(*reg1);
+                    event.mem_read = Some(addr);
                 }
-                Verb::Shl(op1, op2) => {
-                    let ra = op1.to_reg();
-                    if let Operand::Reg(rb) = op2 {
-                        self.regs[ra.to_id() as usize] <<= self.regs[rb.to_id() as usize];
-                    } else {
-                        self.regs[ra.to_id() as usize] <<= op2.to_imm() as i16;
-                    }
+                (Operand::MemAtReg(reg1), Operand::Reg(reg2)) => {
+                    let addr = self.regs[reg1.to_id() as usize] as u16;
+                    self.write_mem(addr, self.regs[reg2.to_id() as usize]);
+                    event.mem_written = Some(addr);
                 }
-                Verb::Shr(op1, op2) => {
-                    let ra = op1.to_reg();
-                    if let Operand::Reg(rb) = op2 {
-                        let a = self.regs[ra.to_id() as usize] as u16;
-                        self.regs[ra.to_id() as usize] =
-                            (a >> self.regs[rb.to_id() as usize]) as i16;
-                    } else {
-                        let a = self.regs[ra.to_id() as usize] as u16;
-                        self.regs[ra.to_id() as usize] = (a >> op2.to_imm() as i16) as i16;
-                    }
+                _ => {
+                    return Err(EmuFault::MalformedInstruction {
+                        ip: self.ip,
+                        verb: next_instr.clone(),
+                    })
+                }
+            },
+            Verb::Jmp(imm) => {
+                self.ip = imm.to_imm().overflowing_sub(1).0;
+                event.jump_taken = true;
+            }
+            Verb::Jz(imm, reg) | Verb::Jnz(imm, reg) => {
+                let imm = imm.to_imm();
+                let reg = reg.to_reg();
+                let reg_value = self.regs[reg.to_id() as usize];
+
+                let jump_taken = (reg_value == 0) == matches!(next_instr, Verb::Jz(..));
+                if jump_taken {
+                    self.ip = imm.overflowing_sub(1).0;
                 }
-                Verb::Dbg(op1) => {
-                    self.ip += 1;
-                    let next_instr = self.instrs.get(self.ip as usize).unwrap();
-                    match next_instr {
-                        Verb::Dbg(op2) => {
-                            let addr1 = op1.to_imm();
-                            let addr2 = op2.to_imm();
-                            println!("==========");
-                            println!("IP: {}", self.ip);
-                            println!("memory from 0x{:X} to 0x{:X}:", addr1, addr2);
-                            for i in addr1..=addr2 {
-                                println!("{}", self.mem[i as usize]);
-                            }
-                            println!("==========");
+                event.jump_taken = jump_taken;
+            }
+
+            Verb::Add(op1, op2) => {
+                let ra = op1.to_reg();
+                if let Operand::Reg(rb) = op2 {
+                    self.regs[ra.to_id() as usize] = self.regs[ra.to_id() as usize]
+                        .overflowing_add(self.regs[rb.to_id() as usize])
+                        .0;
+                } else {
+                    self.regs[ra.to_id() as usize] = self.regs[ra.to_id() as usize]
+                        .overflowing_add(op2.to_imm() as i16)
+                        .0;
+                }
+                event.reg_written = Some(ra);
+            }
+            Verb::Sub(op1, op2) => {
+                let ra = op1.to_reg();
+                if let Operand::Reg(rb) = op2 {
+                    self.regs[ra.to_id() as usize] = self.regs[ra.to_id() as usize]
+                        .overflowing_sub(self.regs[rb.to_id() as usize])
+                        .0;
+                } else {
+                    self.regs[ra.to_id() as usize] = self.regs[ra.to_id() as usize]
+                        .overflowing_sub(op2.to_imm() as i16)
+                        .0;
+                }
+                event.reg_written = Some(ra);
+            }
+            Verb::And(op1, op2) => {
+                let ra = op1.to_reg();
+                if let Operand::Reg(rb) = op2 {
+                    self.regs[ra.to_id() as usize] &= self.regs[rb.to_id() as usize];
+                } else {
+                    self.regs[ra.to_id() as usize] &= op2.to_imm() as i16;
+                }
+                event.reg_written = Some(ra);
+            }
+            Verb::Or(op1, op2) => {
+                let ra = op1.to_reg();
+                if let Operand::Reg(rb) = op2 {
+                    self.regs[ra.to_id() as usize] |= self.regs[rb.to_id() as usize];
+                } else {
+                    self.regs[ra.to_id() as usize] |= op2.to_imm() as i16;
+                }
+                event.reg_written = Some(ra);
+            }
+            Verb::Not(ra) => {
+                let ra = ra.to_reg();
+                self.regs[ra.to_id() as usize] = !self.regs[ra.to_id() as usize];
+                event.reg_written = Some(ra);
+            }
+            Verb::Shl(op1, op2) | Verb::Shr(op1, op2) => {
+                let ra = op1.to_reg();
+                let amount = match op2 {
+                    Operand::Reg(rb) => self.regs[rb.to_id() as usize] as u16,
+                    _ => op2.to_imm(),
+                };
+                // shifting by 16 or more clears the register, like in the Verilog
+                let a = self.regs[ra.to_id() as usize] as u16;
+                let res = match next_instr {
+                    Verb::Shl(..) => a.checked_shl(amount as u32),
+                    _ => a.checked_shr(amount as u32),
+                };
+                self.regs[ra.to_id() as usize] = res.unwrap_or(0) as i16;
+                event.reg_written = Some(ra);
+            }
+            Verb::Dbg(op1) => {
+                self.ip += 1;
+                let next_instr = self.fetch(self.ip)?;
+                match next_instr {
+                    Verb::Dbg(op2) => {
+                        let addr1 = op1.to_imm();
+                        let addr2 = op2.to_imm();
+                        self.print_line("==========".to_string());
+                        self.print_line(format!("IP: {}", self.ip));
+                        self.print_line(format!("memory from 0x{:X} to 0x{:X}:", addr1, addr2));
+                        for i in addr1..=addr2 {
+                            self.print_line(format!("{}", self.mem[i as usize]));
                         }
-                        _ => panic!("dbg instruction not followed by another!"),
+                        self.print_line("==========".to_string());
                     }
-                }
-                Verb::DbgRegs => {
-                    println!("==========");
-                    println!("IP: {}", self.ip);
-                    println!("regs: {:?}", self.regs);
-                    println!("==========");
-                }
-                Verb::Nop => {}
-                Verb::Halt => {
-                    if !self.halted {
-                        self.halted = true;
-                        println!("program halting.");
+                    _ => {
+                        return Err(EmuFault::UnpairedDbg {
+                            ip: event.ip,
+                            verb: event.verb,
+                        })
                     }
-                    return;
                 }
-                Verb::Call(imm) => {
-                    // store current IP value
-                    let rsp = self.regs[0] as u16 as usize;
-                    self.mem[rsp] = self.ip as i16;
-                    // increment rsp
-                    self.regs[0] = self.regs[0].overflowing_add(1).0;
+            }
+            Verb::DbgRegs => {
+                self.print_line("==========".to_string());
+                self.print_line(format!("IP: {}", self.ip));
+                self.print_line(format!("regs: {:?}", self.regs));
+                self.print_line("==========".to_string());
+            }
+            Verb::Nop => {}
+            Verb::Halt => {
+                self.halted = true;
+                self.print_line("program halting.".to_string());
+                event.halted = true;
+                return Ok(event);
+            }
+            Verb::Call(imm) => {
+                // store current IP value
+                let rsp = self.regs[0] as u16 as usize;
+                self.write_mem(rsp as u16, self.ip as i16);
+                // increment rsp
+                self.regs[0] = self.regs[0].overflowing_add(1).0;
+
+                // jump to new address minus one (because IP gets incremented at end of each cycle)
+                self.ip = imm.to_imm().overflowing_sub(1).0;
+
+                event.reg_written = Some(Reg::R0);
+                event.mem_written = Some(rsp as u16);
+                event.jump_taken = true;
+
+                self.call_stack.push(CallFrame {
+                    call_site: event.ip,
+                    target: imm.to_imm(),
+                });
+            }
+            Verb::Ret => {
+                // decrement rsp
+                self.regs[0] = self.regs[0].overflowing_sub(1).0;
+                // read address to return to
+                let rsp = self.regs[0] as u16 as usize;
+                // jump there, let execution continue (so we jump to ret addr and not (ret addr) - 1)
+                self.ip = self.read_mem(rsp as u16) as u16;
+
+                event.reg_written = Some(Reg::R0);
+                event.mem_read = Some(rsp as u16);
+                event.jump_taken = true;
+
+                self.call_stack.pop();
+            }
+            Verb::Ei => self.interrupts_enabled = true,
+            Verb::Di => self.interrupts_enabled = false,
+            Verb::Iret => {
+                self.regs[0] = self.regs[0].overflowing_sub(1).0;
+                let rsp = self.regs[0] as u16;
+                // an interrupt pushes the address of the instruction it interrupted, so that
+                // instruction runs next
+                self.ip = self.read_mem(rsp) as u16;
+                self.interrupts_enabled = true;
+
+                event.reg_written = Some(Reg::R0);
+                event.mem_read = Some(rsp);
+                event.jump_taken = true;
+
+                self.call_stack.pop();
+                return Ok(event);
+            }
+        }
+        self.ip = self.ip.overflowing_add(1).0;
+        Ok(event)
+    }
+
+    fn deliver_interrupt(&mut self) -> Result<StepEvent, EmuFault> {
+        // takes the place of an instruction: pushes the IP like `call` does and jumps to the
+        // handler, with further interrupts disabled until it returns with iret
+        let vector = self.mem[INT_VECTOR_ADDR as usize] as u16;
+        let verb = Verb::Call(Operand::Imm(vector));
+        self.check_data_access(&verb)?;
+        self.instructions_executed += 1;
+
+        let rsp = self.regs[0] as u16;
+        self.write_mem(rsp, self.ip as i16);
+        self.regs[0] = self.regs[0].overflowing_add(1).0;
+        self.call_stack.push(CallFrame {
+            call_site: self.ip,
+            target: vector,
+        });
+        let event = StepEvent {
+            ip: self.ip,
+            verb,
+            reg_written: Some(Reg::R0),
+            mem_read: None,
+            mem_written: Some(rsp),
+            jump_taken: true,
+            halted: false,
+            interrupt: true,
+        };
+        self.ip = vector;
+        self.interrupts_enabled = false;
+        Ok(event)
+    }
+
+    fn check_instr(&mut self, verb: &Verb) -> Result<(), EmuFault> {
+        // done before the instruction runs, so that a fault leaves the machine as it was
+        let ip = self.ip;
+        let is_reg = |op: &Operand| matches!(op, Operand::Reg(_));
+        let is_imm = |op: &Operand| matches!(op, Operand::Imm(_));
+        let well_formed = match verb {
+            Verb::Mov(op1, op2) => matches!(
+                (op1, op2),
+                (Operand::Reg(_), Operand::Imm(_))
+                    | (Operand::Reg(_), Operand::MemAtImm(_))
+                    | (Operand::MemAtImm(_), Operand::Reg(_))
+                    | (Operand::Reg(_), Operand::Reg(_))
+                    | (Operand::Reg(_), Operand::MemAtReg(_))
+                    | (Operand::MemAtReg(_), Operand::Reg(_))
+            ),
+            Verb::Jmp(op) | Verb::Call(op) | Verb::Dbg(op) => is_imm(op),
+            Verb::Jz(op1, op2) | Verb::Jnz(op1, op2) => is_imm(op1) && is_reg(op2),
+            Verb::Add(op1, op2)
+            | Verb::Sub(op1, op2)
+            | Verb::And(op1, op2)
+            | Verb::Or(op1, op2)
+            | Verb::Shl(op1, op2)
+            | Verb::Shr(op1, op2) => is_reg(op1) && (is_reg(op2) || is_imm(op2)),
+            Verb::Not(op) => is_reg(op),
+            Verb::Ret
+            | Verb::DbgRegs
+            | Verb::Nop
+            | Verb::Halt
+            | Verb::Ei
+            | Verb::Di
+            | Verb::Iret => true,
+        };
+        if !well_formed {
+            return Err(EmuFault::MalformedInstruction {
+                ip,
+                verb: verb.clone(),
+            });
+        }
+
+        if let Verb::Dbg(_) = verb {
+            if !matches!(
+                self.fetch(ip.wrapping_add(1)),
+                Ok(Verb::Dbg(Operand::Imm(_)))
+            ) {
+                return Err(EmuFault::UnpairedDbg {
+                    ip,
+                    verb: verb.clone(),
+                });
+            }
+        }
+        if matches!(verb, Verb::Ret | Verb::Iret) && self.regs[0] == 0 {
+            return Err(EmuFault::StackUnderflow {
+                ip,
+                verb: verb.clone(),
+            });
+        }
+        self.check_data_access(verb)
+    }
+
+    fn check_data_access(&mut self, verb: &Verb) -> Result<(), EmuFault> {
+        let reg_value = |reg: &Reg| self.regs[reg.to_id() as usize] as u16;
+        let addr = match verb {
+            Verb::Mov(Operand::MemAtImm(imm), _) | Verb::Mov(_, Operand::MemAtImm(imm)) => *imm,
+            Verb::Mov(Operand::MemAtReg(reg), _) | Verb::Mov(_, Operand::MemAtReg(reg)) => {
+                reg_value(reg)
+            }
+            Verb::Call(_) => reg_value(&Reg::R0),
+            Verb::Ret | Verb::Iret => reg_value(&Reg::R0).wrapping_sub(1),
+            _ => return Ok(()),
+        };
+        if self.profile.has_data_addr(addr) {
+            return Ok(());
+        }
 
-                    // jump to new address minus one (because IP gets incremented at end of each cycle)
-                    self.ip = imm.to_imm().overflowing_sub(1).0;
+        let fault = EmuFault::DataOutOfRange {
+            ip: self.ip,
+            verb: verb.clone(),
+            addr,
+            profile: self.profile,
+        };
+        match self.out_of_range {
+            OutOfRange::Fault => Err(fault),
+            OutOfRange::Warn => {
+                if self.warned_addrs.insert(addr) {
+                    self.print_line(format!("warning: {}", fault));
                 }
-                Verb::Ret => {
-                    // decrement rsp
-                    self.regs[0] = self.regs[0].overflowing_sub(1).0;
-                    // read address to return to
-                    let rsp = self.regs[0] as u16 as usize;
-                    // jump there, let execution continue (so we jump to ret addr and not (ret addr) - 1)
-                    self.ip = self.mem[rsp] as u16;
+                Ok(())
+            }
+        }
+    }
+
+    fn read_mem(&mut self, addr: u16) -> i16 {
+        let value = self.bus.read(
+            addr,
+            &mut DeviceMem::new(&mut self.mem, &mut self.device_writes),
+        );
+        self.read_value = Some(value);
+        value
+    }
+
+    fn write_mem(&mut self, addr: u16, value: i16) {
+        self.stored_value = Some(value);
+        self.bus.write(
+            addr,
+            value,
+            &mut DeviceMem::new(&mut self.mem, &mut self.device_writes),
+        );
+    }
+
+    pub fn get_state(&self) -> MachineState {
+        MachineState {
+            program_hash: program_hash(&self.instrs),
+            ip: self.ip,
+            halted: self.halted,
+            interrupts_enabled: self.interrupts_enabled,
+            instructions_executed: self.instructions_executed,
+            regs: self.regs,
+            switches: self.get_switch_states(),
+            buttons: self.get_button_states(),
+            mem: self.mem.to_vec(),
+            call_stack: self.call_stack.clone(),
+        }
+    }
+
+    pub fn set_state(&mut self, state: &MachineState) -> Result<(), String> {
+        if state.program_hash != program_hash(&self.instrs) {
+            return Err("the state was saved from a different program".to_string());
+        }
+        if state.mem.len() != self.mem.len() {
+            return Err(format!("expected {} words of memory", self.mem.len()));
+        }
+        self.ip = state.ip;
+        self.halted = state.halted;
+        self.interrupts_enabled = state.interrupts_enabled;
+        self.instructions_executed = state.instructions_executed;
+        self.regs = state.regs;
+        self.mem.copy_from_slice(&state.mem);
+        self.mem[SWITCHES_ADDR as usize] = state.switches;
+        self.mem[BUTTONS_ADDR as usize] = state.buttons;
+        self.call_stack = state.call_stack.clone();
+        self.forget_history();
+        Ok(())
+    }
+
+    pub fn enable_history(&mut self) {
+        self.history.get_or_insert_with(History::default);
+    }
+
+    fn forget_history(&mut self) {
+        // state changed from outside can't be replayed, so the history before it is dropped
+        if self.history.is_some() {
+            self.history = Some(History::default());
+        }
+    }
+
+    pub fn get_history_start(&self) -> Option<u64> {
+        self.history.as_ref().and_then(History::oldest_count)
+    }
+
+    pub fn last_write(&self, addr: u16) -> Option<u64> {
+        self.history
+            .as_ref()
+            .and_then(|history| history.last_write(addr))
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            count: self.instructions_executed,
+            ip: self.ip,
+            halted: self.halted,
+            interrupts_enabled: self.interrupts_enabled,
+            regs: self.regs,
+            mem: self.mem.to_vec(),
+            call_stack: self.call_stack.clone(),
+            script_position: self.get_script_position(),
+            uart_received: self.uart.borrow().get_received_count(),
+        }
+    }
+
+    fn undo(&mut self) -> Option<UndoEntry> {
+        let history = self.history.as_mut()?;
+        let entry = history.pop()?;
+        while let Some(write) = history.pop_device_write(entry.count) {
+            self.mem[write.addr as usize] = write.old;
+        }
+        self.instructions_executed = entry.count;
+        self.ip = entry.ip;
+        // a halted CPU does not step, so it was running before any instruction
+        self.halted = false;
+        self.interrupts_enabled = entry.interrupts_enabled;
+        self.set_script_position(entry.script_position);
+        self.uart
+            .borrow_mut()
+            .set_received_count(entry.uart_received);
+        if let Some((reg, value)) = entry.reg {
+            self.regs[reg.to_id() as usize] = value;
+        }
+        match entry.call_stack {
+            CallStackChange::Pushed => {
+                self.call_stack.pop();
+            }
+            CallStackChange::Popped(frame) => self.call_stack.push(frame),
+            CallStackChange::None => {}
+        }
+        Some(entry)
+    }
+
+    pub fn reverse_step(&mut self) -> Result<(), String> {
+        match self.undo() {
+            Some(_) => Ok(()),
+            None => Err("no more history to go back through".to_string()),
+        }
+    }
+
+    pub fn rewind_to(&mut self, count: u64) -> Result<(), String> {
+        // goes back to the state from before instruction number `count` + 1 ran
+        let start = self.get_history_start();
+        if count > self.instructions_executed || start.is_none_or(|start| count < start) {
+            return Err(format!(
+                "instruction {} is not in the recorded history",
+                count
+            ));
+        }
+
+        // undoing one instruction at a time is fine for short distances. Further back,
+        // restore a snapshot and run forward from it instead.
+        let history = self.history.as_ref().unwrap();
+        let snapshot = history
+            .snapshot_at_or_before(count)
+            .filter(|s| count - s.count < self.instructions_executed - count);
+        let Some(snapshot) = snapshot else {
+            while self.instructions_executed > count {
+                self.undo();
+            }
+            return Ok(());
+        };
+
+        self.instructions_executed = snapshot.count;
+        self.ip = snapshot.ip;
+        self.halted = snapshot.halted;
+        self.interrupts_enabled = snapshot.interrupts_enabled;
+        self.regs = snapshot.regs;
+        self.mem.copy_from_slice(&snapshot.mem);
+        self.call_stack = snapshot.call_stack.clone();
+        self.uart
+            .borrow_mut()
+            .set_received_count(snapshot.uart_received);
+        let (snapshot_count, script_position) = (snapshot.count, snapshot.script_position);
+        self.set_script_position(script_position);
+        self.history.as_mut().unwrap().truncate_to(snapshot_count);
+
+        let tracer = self.tracer.take();
+        self.replaying = true;
+        let mut res = Ok(());
+        while self.instructions_executed < count {
+            if let Err(fault) = self.step() {
+                res = Err(fault.to_string());
+                break;
+            }
+        }
+        self.replaying = false;
+        self.tracer = tracer;
+        res
+    }
+
+    pub fn reverse_run(&mut self) -> StopReason {
+        // runs backwards until a breakpoint or watchpoint is reached, or the history runs out.
+        // Stops before the instruction that hit the watchpoint.
+        while let Some(entry) = self.undo() {
+            if self.breakpoints.contains(&self.ip) {
+                return StopReason::Breakpoint(self.ip);
+            }
+            let accesses = [
+                (entry.mem_read, WatchKind::Read),
+                (entry.mem_written, WatchKind::Write),
+            ];
+            for (addr, access) in accesses {
+                match addr.and_then(|addr| self.watchpoints.get(&addr).map(|kind| (addr, kind))) {
+                    Some((addr, kind)) if kind.matches(access) => {
+                        return StopReason::Watchpoint {
+                            addr,
+                            access,
+                            ip: entry.ip,
+                        }
+                    }
+                    _ => {}
                 }
             }
-            self.ip = self.ip.overflowing_add(1).0;
         }
+        StopReason::StartOfHistory
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn add_watchpoint(&mut self, addr: u16, kind: WatchKind) {
+        self.watchpoints.insert(addr, kind);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn get_breakpoints(&self) -> &HashSet<u16> {
+        &self.breakpoints
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u16) -> bool {
+        self.watchpoints.remove(&addr).is_some()
+    }
+
+    pub fn get_watchpoints(&self) -> &HashMap<u16, WatchKind> {
+        &self.watchpoints
+    }
+
+    pub fn get_call_stack(&self) -> &[CallFrame] {
+        &self.call_stack
+    }
+
+    pub fn run(&mut self, max_instructions: u64) -> Result<StopReason, EmuFault> {
+        self.run_until(max_instructions, |_, _| false)
+    }
+
+    pub fn run_until<F>(
+        &mut self,
+        max_instructions: u64,
+        mut stop: F,
+    ) -> Result<StopReason, EmuFault>
+    where
+        F: FnMut(&CpuEmu, &StepEvent) -> bool,
+    {
+        // the breakpoint at the current IP is skipped, so that we can continue from a breakpoint
+        for i in 0..max_instructions {
+            if i > 0 && self.breakpoints.contains(&self.ip) {
+                return Ok(StopReason::Breakpoint(self.ip));
+            }
+
+            let event = self.step()?;
+            if event.halted {
+                return Ok(StopReason::Halted);
+            }
+            if let Some(stop) = self.check_watchpoints(&event) {
+                return Ok(stop);
+            }
+            if stop(self, &event) {
+                return Ok(StopReason::Condition);
+            }
+        }
+        // callers that run in chunks would otherwise skip a breakpoint that ends a chunk
+        if max_instructions > 0 && self.breakpoints.contains(&self.ip) {
+            return Ok(StopReason::Breakpoint(self.ip));
+        }
+        Ok(StopReason::BudgetExhausted)
+    }
+
+    fn check_watchpoints(&self, event: &StepEvent) -> Option<StopReason> {
+        let accesses = [
+            (event.mem_read, WatchKind::Read),
+            (event.mem_written, WatchKind::Write),
+        ];
+        for (addr, access) in accesses {
+            if let Some(addr) = addr {
+                match self.watchpoints.get(&addr) {
+                    Some(kind) if kind.matches(access) => {
+                        return Some(StopReason::Watchpoint {
+                            addr,
+                            access,
+                            ip: event.ip,
+                        })
+                    }
+                    _ => {}
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::SNAPSHOT_INTERVAL;
+
+    fn scripted_cpu(script: &str) -> CpuEmu {
+        // reads the buttons and counts in r2, forever
+        let program = [
+            Verb::Mov(Operand::Reg(Reg::R1), Operand::MemAtImm(BUTTONS_ADDR)),
+            Verb::Add(Operand::Reg(Reg::R2), Operand::Imm(1)),
+            Verb::Jmp(Operand::Imm(0)),
+        ];
+        let mut cpu_emulator = CpuEmu::new(program.iter().map(Verb::to_bytes).collect());
+        cpu_emulator.set_input_script(InputScript::parse(script).unwrap());
+        cpu_emulator.enable_history();
+        cpu_emulator
+    }
+
+    #[test]
+    fn scripted_inputs_are_undone_with_their_step() {
+        let mut cpu_emulator = scripted_cpu("at 5: press A; at 5: sw 0x3; at 9: release A");
+        cpu_emulator.run(20).unwrap();
+        let (regs, mem) = (*cpu_emulator.get_regs(), cpu_emulator.get_mem().to_vec());
+        assert_eq!(cpu_emulator.get_history_start(), Some(0));
+
+        cpu_emulator.rewind_to(7).unwrap();
+        assert_eq!(cpu_emulator.get_button_states(), 4);
+        assert_eq!(cpu_emulator.get_switch_states(), 3);
+        cpu_emulator.rewind_to(5).unwrap();
+        assert_eq!(cpu_emulator.get_button_states(), 0);
+        assert_eq!(cpu_emulator.get_switch_states(), 0);
+        assert_eq!(cpu_emulator.get_pending_interrupts(), 0);
+
+        // going forward again applies the events again
+        cpu_emulator.run(15).unwrap();
+        assert_eq!(*cpu_emulator.get_regs(), regs);
+        assert_eq!(cpu_emulator.get_mem(), &mem[..]);
+    }
+
+    #[test]
+    fn stores_to_devices_are_undone() {
+        let program = [
+            Verb::Mov(Operand::Reg(Reg::R1), Operand::Imm(1)),
+            // acknowledges the first of two pending interrupts
+            Verb::Mov(Operand::MemAtImm(INT_CAUSE_ADDR), Operand::Reg(Reg::R1)),
+            Verb::Mov(Operand::MemAtImm(0x10), Operand::Reg(Reg::R1)),
+            Verb::Halt,
+        ];
+        let mut cpu_emulator = CpuEmu::new(program.iter().map(Verb::to_bytes).collect());
+        cpu_emulator.set_mem(INT_CAUSE_ADDR, 0b11);
+        cpu_emulator.enable_history();
+        let mem = cpu_emulator.get_mem().to_vec();
+
+        assert_eq!(cpu_emulator.run(10), Ok(StopReason::Halted));
+        assert_eq!(cpu_emulator.get_pending_interrupts(), 0b10);
+        assert_eq!(cpu_emulator.get_mem()[0x10], 1);
+
+        cpu_emulator.rewind_to(0).unwrap();
+        assert_eq!(cpu_emulator.get_mem(), &mem[..]);
+    }
+
+    #[test]
+    fn received_uart_bytes_are_undone_with_their_step() {
+        let program = [Verb::Nop, Verb::Jmp(Operand::Imm(0))];
+        let mut cpu_emulator = CpuEmu::new(program.iter().map(Verb::to_bytes).collect());
+        cpu_emulator.enable_history();
+        cpu_emulator.send_uart_input(b"hi");
+        let mem = cpu_emulator.get_mem().to_vec();
+
+        cpu_emulator.step().unwrap();
+        assert_eq!(cpu_emulator.get_mem()[UART_RX_ADDR as usize], b'h' as i16);
+        assert_eq!(
+            cpu_emulator.get_mem()[UART_STATUS_ADDR as usize],
+            UART_RX_FULL
+        );
+        assert_eq!(cpu_emulator.get_pending_interrupts(), UART_INTERRUPT);
+        assert_eq!(cpu_emulator.uart.borrow().input, b"i");
+        assert_eq!(cpu_emulator.get_history_start(), Some(0));
+
+        cpu_emulator.reverse_step().unwrap();
+        assert_eq!(cpu_emulator.get_mem(), &mem[..]);
+        assert_eq!(cpu_emulator.uart.borrow().input, b"hi");
+
+        // the same byte arrives again
+        cpu_emulator.step().unwrap();
+        assert_eq!(cpu_emulator.get_mem()[UART_RX_ADDR as usize], b'h' as i16);
+        assert_eq!(cpu_emulator.uart.borrow().input, b"i");
+    }
+
+    #[test]
+    fn a_faulting_step_takes_back_its_inputs() {
+        let program = [Verb::Jmp(Operand::Imm(0x10))];
+        let mut cpu_emulator = CpuEmu::new(program.iter().map(Verb::to_bytes).collect());
+        cpu_emulator.enable_history();
+        cpu_emulator.step().unwrap();
+        cpu_emulator.send_uart_input(b"hi");
+        let mem = cpu_emulator.get_mem().to_vec();
+
+        let fault = cpu_emulator.step().unwrap_err();
+        assert_eq!(fault, EmuFault::IpOutOfRange { ip: 0x10 });
+        assert_eq!(cpu_emulator.get_mem(), &mem[..]);
+        assert_eq!(cpu_emulator.uart.borrow().input, b"hi");
+        assert!(cpu_emulator.device_writes.is_empty());
+
+        // the step before the fault is still the last one in the history
+        cpu_emulator.reverse_step().unwrap();
+        assert_eq!(cpu_emulator.get_ip(), 0);
+        assert_eq!(
+            cpu_emulator.reverse_step(),
+            Err("no more history to go back through".to_string())
+        );
+        assert_eq!(cpu_emulator.get_mem(), &mem[..]);
+    }
+
+    #[test]
+    fn a_halted_cpu_stays_halted() {
+        let program = [Verb::Nop, Verb::Halt];
+        let mut cpu_emulator = CpuEmu::new(program.iter().map(Verb::to_bytes).collect());
+        assert_eq!(cpu_emulator.run(10), Ok(StopReason::Halted));
+        let (count, cycles) = (
+            cpu_emulator.get_instructions_executed(),
+            cpu_emulator.get_elapsed_cycles(),
+        );
+        assert_eq!(count, 2);
+
+        cpu_emulator.run_for(Duration::from_secs(1)).unwrap();
+        assert!(cpu_emulator.step().unwrap().halted);
+        assert_eq!(cpu_emulator.get_instructions_executed(), count);
+        assert_eq!(cpu_emulator.get_elapsed_cycles(), cycles);
+        assert_eq!(cpu_emulator.get_ip(), 1);
+    }
+
+    #[test]
+    fn snapshots_restore_the_script_position() {
+        let at = SNAPSHOT_INTERVAL + 10;
+        let mut cpu_emulator = scripted_cpu(&format!("at 3: press A; at {}: release A", at));
+        cpu_emulator.run(at + 100).unwrap();
+        let mem = cpu_emulator.get_mem().to_vec();
+
+        // far enough back to start from the snapshot rather than undo every step
+        cpu_emulator.rewind_to(SNAPSHOT_INTERVAL + 1).unwrap();
+        assert_eq!(cpu_emulator.get_button_states(), 4);
+        cpu_emulator.run(at + 99 - SNAPSHOT_INTERVAL).unwrap();
+        assert_eq!(cpu_emulator.get_button_states(), 0);
+        assert_eq!(cpu_emulator.get_mem(), &mem[..]);
+    }
+
+    #[test]
+    fn snapshots_restore_the_uart_input() {
+        // takes a byte every 3 instructions
+        let program = [
+            Verb::Mov(Operand::Reg(Reg::R1), Operand::MemAtImm(UART_RX_ADDR)),
+            Verb::Add(Operand::Reg(Reg::R2), Operand::Imm(1)),
+            Verb::Jmp(Operand::Imm(0)),
+        ];
+        let mut cpu_emulator = CpuEmu::new(program.iter().map(Verb::to_bytes).collect());
+        cpu_emulator.enable_history();
+        let input: Vec<u8> = (0..=255).cycle().take(SNAPSHOT_INTERVAL as usize).collect();
+        cpu_emulator.send_uart_input(&input);
+        cpu_emulator.run(SNAPSHOT_INTERVAL + 1).unwrap();
+        let waiting = cpu_emulator.uart.borrow().input.clone();
+        cpu_emulator.run(99).unwrap();
+        let (mem, left) = (
+            cpu_emulator.get_mem().to_vec(),
+            cpu_emulator.uart.borrow().input.clone(),
+        );
+
+        cpu_emulator.rewind_to(SNAPSHOT_INTERVAL + 1).unwrap();
+        assert_eq!(cpu_emulator.uart.borrow().input, waiting);
+        cpu_emulator.run(99).unwrap();
+        assert_eq!(cpu_emulator.get_mem(), &mem[..]);
+        assert_eq!(cpu_emulator.uart.borrow().input, left);
     }
 }
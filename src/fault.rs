@@ -0,0 +1,103 @@
+use std::fmt;
+
+use crate::hardware::HardwareProfile;
+use crate::instr_repr::{DecodeError, Verb};
+
+// Faults are detected before the faulting instruction changes anything, so the machine can
+// still be inspected afterwards, and stepping again gives the same fault.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum EmuFault {
+    // the IP left the program, e.g. after a missing halt or a jump to a bad address
+    IpOutOfRange {
+        ip: u16,
+    },
+    // the instruction word does not decode
+    BadInstruction {
+        ip: u16,
+        error: DecodeError,
+    },
+    // decoded, but with operands the instruction does not take
+    MalformedInstruction {
+        ip: u16,
+        verb: Verb,
+    },
+    // dbg dumps the memory between its operand and the operand of the next instruction
+    UnpairedDbg {
+        ip: u16,
+        verb: Verb,
+    },
+    // ret or iret with R0 at 0, so there is no return address to pop
+    StackUnderflow {
+        ip: u16,
+        verb: Verb,
+    },
+    DataOutOfRange {
+        ip: u16,
+        verb: Verb,
+        addr: u16,
+        profile: HardwareProfile,
+    },
+}
+
+impl EmuFault {
+    pub fn ip(&self) -> u16 {
+        match self {
+            EmuFault::IpOutOfRange { ip }
+            | EmuFault::BadInstruction { ip, .. }
+            | EmuFault::MalformedInstruction { ip, .. }
+            | EmuFault::UnpairedDbg { ip, .. }
+            | EmuFault::StackUnderflow { ip, .. }
+            | EmuFault::DataOutOfRange { ip, .. } => *ip,
+        }
+    }
+
+    pub fn instr(&self) -> Option<&Verb> {
+        match self {
+            EmuFault::IpOutOfRange { .. } | EmuFault::BadInstruction { .. } => None,
+            EmuFault::MalformedInstruction { verb, .. }
+            | EmuFault::UnpairedDbg { verb, .. }
+            | EmuFault::StackUnderflow { verb, .. }
+            | EmuFault::DataOutOfRange { verb, .. } => Some(verb),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            EmuFault::IpOutOfRange { .. } => {
+                "program execution continued into undefined instructions!".to_string()
+            }
+            EmuFault::BadInstruction { error, .. } => {
+                format!("could not decode instruction: {}", error)
+            }
+            EmuFault::MalformedInstruction { .. } => {
+                "instruction has operands it does not take".to_string()
+            }
+            EmuFault::UnpairedDbg { .. } => "dbg instruction not followed by another!".to_string(),
+            EmuFault::StackUnderflow { verb, .. } => format!(
+                "stack underflow: {} with R0 at 0, nothing to return to",
+                verb
+            ),
+            EmuFault::DataOutOfRange { addr, profile, .. } => format!(
+                "data memory access at 0x{:X}, but the {} only has {} data words",
+                addr,
+                profile.name(),
+                profile.data_words().unwrap_or(65536)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for EmuFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.instr() {
+            Some(verb) => write!(
+                f,
+                "{} (IP 0x{:X}: {})",
+                self.message(),
+                self.ip(),
+                verb.to_string().trim_end()
+            ),
+            None => write!(f, "{} (IP 0x{:X})", self.message(), self.ip()),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct Timings {
+    frames: u32,
+    frame: Duration,
+    monitor: Duration,
+    uploads: u32,
+}
+
+impl Timings {
+    fn summary(&self) -> String {
+        let frames = self.frames.max(1) as f64;
+        format!(
+            "{} frames, {:.3} ms per frame, {:.3} ms drawing the monitor",
+            self.frames,
+            self.frame.as_secs_f64() * 1000.0 / frames,
+            self.monitor.as_secs_f64() * 1000.0 / frames
+        )
+    }
+}
+
+// --bench-frames: the window draws the monitor with a rectangle per pixel for the given number of
+// frames, then as many from the texture, and compares how long the frames took
+pub struct FrameBench {
+    frames: u32,
+    // the end of the previous frame, none before the first one has been shown
+    last_frame: Option<Instant>,
+    rects: Timings,
+    texture: Timings,
+}
+
+impl FrameBench {
+    pub fn new(frames: u32) -> Self {
+        FrameBench {
+            frames,
+            last_frame: None,
+            rects: Timings::default(),
+            texture: Timings::default(),
+        }
+    }
+
+    pub fn is_drawing_rects(&self) -> bool {
+        self.rects.frames < self.frames
+    }
+
+    pub fn record(&mut self, monitor: Duration, uploaded: bool) -> bool {
+        // called after every frame, returns whether both ways of drawing have been measured. The
+        // first frame also includes opening the window, so it is left out.
+        let now = Instant::now();
+        let Some(last_frame) = self.last_frame.replace(now) else {
+            return false;
+        };
+        let timings = if self.is_drawing_rects() {
+            &mut self.rects
+        } else {
+            &mut self.texture
+        };
+        timings.frames += 1;
+        timings.frame += now - last_frame;
+        timings.monitor += monitor;
+        timings.uploads += uploaded as u32;
+        self.texture.frames == self.frames
+    }
+
+    pub fn print_report(&self) {
+        println!("rectangles: {}", self.rects.summary());
+        println!(
+            "texture:    {}, {} uploads",
+            self.texture.summary(),
+            self.texture.uploads
+        );
+    }
+}
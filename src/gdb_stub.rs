@@ -0,0 +1,513 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::emu::{CpuEmu, StopReason, WatchKind};
+use crate::fault::EmuFault;
+use crate::instr_repr::Reg;
+
+// R0-R15, then IP
+const NUM_REGS: usize = 17;
+
+// how many instructions to run between checks for a ctrl-c from the client
+const RUN_CHUNK: u64 = 10_000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.asm_emu.cpu">
+    <reg name="r0" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="r1" bitsize="16" type="uint16"/>
+    <reg name="r2" bitsize="16" type="uint16"/>
+    <reg name="r3" bitsize="16" type="uint16"/>
+    <reg name="r4" bitsize="16" type="uint16"/>
+    <reg name="r5" bitsize="16" type="uint16"/>
+    <reg name="r6" bitsize="16" type="uint16"/>
+    <reg name="r7" bitsize="16" type="uint16"/>
+    <reg name="r8" bitsize="16" type="uint16"/>
+    <reg name="r9" bitsize="16" type="uint16"/>
+    <reg name="r10" bitsize="16" type="uint16"/>
+    <reg name="r11" bitsize="16" type="uint16"/>
+    <reg name="r12" bitsize="16" type="uint16"/>
+    <reg name="r13" bitsize="16" type="uint16"/>
+    <reg name="r14" bitsize="16" type="uint16"/>
+    <reg name="r15" bitsize="16" type="uint16"/>
+    <reg name="ip" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// Data memory is made of 16 bit words, but gdb addresses bytes. Data word N is
+// exposed at gdb address 2*N, little endian. Breakpoints and the IP register use
+// instruction addresses, which live in the separate instruction memory.
+pub struct GdbStub {
+    cpu_emulator: CpuEmu,
+    no_ack_mode: bool,
+}
+
+enum Reply {
+    Packet(String),
+    Detach,
+}
+
+impl GdbStub {
+    pub fn new(mut cpu_emulator: CpuEmu) -> Self {
+        cpu_emulator.enable_history();
+        GdbStub {
+            cpu_emulator,
+            no_ack_mode: false,
+        }
+    }
+
+    pub fn listen(&mut self, port: u16) -> io::Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("waiting for gdb to connect on 127.0.0.1:{}", port);
+        let (stream, addr) = listener.accept()?;
+        println!("gdb connected from {}", addr);
+        self.serve(stream)
+    }
+
+    pub fn serve(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        self.no_ack_mode = false;
+
+        while let Some(packet) = self.read_packet(&mut stream)? {
+            match self.handle_packet(&packet, &mut stream)? {
+                Reply::Packet(reply) => self.write_packet(&mut stream, &reply)?,
+                Reply::Detach => {
+                    self.write_packet(&mut stream, "OK")?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_packet(&self, stream: &mut TcpStream) -> io::Result<Option<String>> {
+        // a packet with a bad checksum is nacked, and the client sends it again
+        let mut byte = [0u8];
+        loop {
+            loop {
+                if stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                match byte[0] {
+                    b'$' => break,
+                    // acks, and ctrl-c while we are already stopped
+                    _ => continue,
+                }
+            }
+
+            let mut data = Vec::new();
+            loop {
+                if stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+            let mut checksum = [0u8; 2];
+            stream.read_exact(&mut checksum)?;
+
+            let expected = u8::from_str_radix(&String::from_utf8_lossy(&checksum), 16).ok();
+            if !self.no_ack_mode {
+                if expected != Some(packet_checksum(&data)) {
+                    stream.write_all(b"-")?;
+                    continue;
+                }
+                stream.write_all(b"+")?;
+            }
+            return Ok(Some(String::from_utf8_lossy(&data).to_string()));
+        }
+    }
+
+    fn write_packet(&self, stream: &mut TcpStream, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:0>2x}", data, packet_checksum(data.as_bytes()));
+        stream.write_all(packet.as_bytes())?;
+        stream.flush()
+    }
+
+    fn handle_packet(&mut self, packet: &str, stream: &mut TcpStream) -> io::Result<Reply> {
+        let reply = match packet.chars().next() {
+            Some('?') => "S05".to_string(),
+            Some('g') => self.read_registers(),
+            Some('G') => self.write_registers(&packet[1..]),
+            Some('p') => self.read_register(&packet[1..]),
+            Some('P') => self.write_register(&packet[1..]),
+            Some('m') => self.read_memory(&packet[1..]),
+            Some('M') => self.write_memory(&packet[1..]),
+            Some('Z') => self.set_breakpoint(&packet[1..], true),
+            Some('z') => self.set_breakpoint(&packet[1..], false),
+            Some('s') => self.single_step(),
+            Some('c') => self.continue_execution(stream)?,
+            Some('b') => self.reverse(&packet[1..]),
+            Some('H') => "OK".to_string(),
+            Some('k') | Some('D') => return Ok(Reply::Detach),
+            Some('q') | Some('Q') => self.query(packet),
+            // unsupported packets get an empty reply
+            _ => String::new(),
+        };
+        Ok(Reply::Packet(reply))
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+;ReverseStep+;ReverseContinue+".to_string();
+        }
+        if packet == "QStartNoAckMode" {
+            self.no_ack_mode = true;
+            return "OK".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = match parse_addr_len(range, ',') {
+                Some(v) => v,
+                None => return "E01".to_string(),
+            };
+            let xml = TARGET_XML.as_bytes();
+            let start = offset.min(xml.len());
+            let end = start.saturating_add(length).min(xml.len());
+            let prefix = if end == xml.len() { "l" } else { "m" };
+            return format!("{}{}", prefix, String::from_utf8_lossy(&xml[start..end]));
+        }
+        match packet {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        let mut res = String::new();
+        for id in 0..NUM_REGS {
+            res.push_str(&encode_word(self.get_register(id)));
+        }
+        res
+    }
+
+    fn write_registers(&mut self, data: &str) -> String {
+        if data.len() < NUM_REGS * 4 || !is_hex(data) {
+            return "E01".to_string();
+        }
+        for id in 0..NUM_REGS {
+            match decode_word(&data[id * 4..id * 4 + 4]) {
+                Some(value) => self.set_register(id, value),
+                None => return "E01".to_string(),
+            }
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        match usize::from_str_radix(args, 16) {
+            Ok(id) if id < NUM_REGS => encode_word(self.get_register(id)),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let (id, value) = match args.split_once('=') {
+            Some(v) => v,
+            None => return "E01".to_string(),
+        };
+        match (usize::from_str_radix(id, 16), decode_word(value)) {
+            (Ok(id), Some(value)) if id < NUM_REGS => {
+                self.set_register(id, value);
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn get_register(&self, id: usize) -> u16 {
+        match id {
+            16 => self.cpu_emulator.get_ip(),
+            _ => self.cpu_emulator.get_regs()[id] as u16,
+        }
+    }
+
+    fn set_register(&mut self, id: usize, value: u16) {
+        match id {
+            16 => self.cpu_emulator.set_ip(value),
+            _ => self
+                .cpu_emulator
+                .set_reg(Reg::from_id(id as u8).unwrap(), value as i16),
+        }
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let (addr, len) = match parse_addr_len(args, ',') {
+            Some(v) => v,
+            None => return "E01".to_string(),
+        };
+        let end = match addr.checked_add(len) {
+            Some(end) => end,
+            None => return "E01".to_string(),
+        };
+        let mem = self.cpu_emulator.get_mem();
+        let mut res = String::new();
+        for byte_addr in addr..end {
+            let word = match mem.get(byte_addr / 2) {
+                Some(word) => *word as u16,
+                None => return "E14".to_string(),
+            };
+            res.push_str(&format!("{:0>2x}", word.to_le_bytes()[byte_addr % 2]));
+        }
+        res
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let (range, data) = match args.split_once(':') {
+            Some(v) => v,
+            None => return "E01".to_string(),
+        };
+        let (addr, len) = match parse_addr_len(range, ',') {
+            Some(v) => v,
+            None => return "E01".to_string(),
+        };
+        let in_range = addr
+            .checked_add(len)
+            .is_some_and(|end| end <= self.cpu_emulator.get_mem().len() * 2);
+        if !in_range || !is_hex(data) || data.len() != len * 2 {
+            return "E01".to_string();
+        }
+
+        for i in 0..len {
+            let byte = match u8::from_str_radix(&data[i * 2..i * 2 + 2], 16) {
+                Ok(byte) => byte,
+                Err(_) => return "E01".to_string(),
+            };
+            let byte_addr = addr + i;
+            let word_addr = (byte_addr / 2) as u16;
+            let mut bytes = (self.cpu_emulator.get_mem()[word_addr as usize] as u16).to_le_bytes();
+            bytes[byte_addr % 2] = byte;
+            self.cpu_emulator
+                .set_mem(word_addr, u16::from_le_bytes(bytes) as i16);
+        }
+        "OK".to_string()
+    }
+
+    fn set_breakpoint(&mut self, args: &str, insert: bool) -> String {
+        // TYPE,ADDR,KIND
+        let mut parts = args.split(',');
+        let (kind, addr) = match (parts.next(), parts.next()) {
+            (Some(kind), Some(addr)) => (kind, addr),
+            _ => return "E01".to_string(),
+        };
+        let addr = match usize::from_str_radix(addr, 16) {
+            Ok(addr) => addr,
+            Err(_) => return "E01".to_string(),
+        };
+
+        let watch_kind = match kind {
+            // software and hardware breakpoints are the same thing here
+            "0" | "1" => {
+                if insert {
+                    self.cpu_emulator.add_breakpoint(addr as u16);
+                } else {
+                    self.cpu_emulator.remove_breakpoint(addr as u16);
+                }
+                return "OK".to_string();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::ReadWrite,
+            _ => return String::new(),
+        };
+        let word_addr = (addr / 2) as u16;
+        if insert {
+            self.cpu_emulator.add_watchpoint(word_addr, watch_kind);
+        } else {
+            self.cpu_emulator.remove_watchpoint(word_addr);
+        }
+        "OK".to_string()
+    }
+
+    fn single_step(&mut self) -> String {
+        match self.cpu_emulator.step() {
+            Ok(event) if event.halted => "W00".to_string(),
+            Ok(_) => "S05".to_string(),
+            Err(_) => "S04".to_string(),
+        }
+    }
+
+    fn reverse(&mut self, args: &str) -> String {
+        // bs and bc, for reverse-stepi and reverse-continue
+        let stop = match args {
+            "s" => match self.cpu_emulator.reverse_step() {
+                Ok(()) => StopReason::Condition,
+                Err(_) => StopReason::StartOfHistory,
+            },
+            "c" => self.cpu_emulator.reverse_run(),
+            _ => return String::new(),
+        };
+        stop_reply(&Ok(stop)).unwrap_or("S05".to_string())
+    }
+
+    fn continue_execution(&mut self, stream: &mut TcpStream) -> io::Result<String> {
+        loop {
+            if let Some(reply) = stop_reply(&self.cpu_emulator.run(RUN_CHUNK)) {
+                return Ok(reply);
+            }
+            if interrupt_requested(stream)? {
+                return Ok("S02".to_string());
+            }
+        }
+    }
+}
+
+fn stop_reply(stop: &Result<StopReason, EmuFault>) -> Option<String> {
+    match stop {
+        Ok(StopReason::BudgetExhausted) | Ok(StopReason::Condition) => None,
+        Ok(StopReason::Breakpoint(_)) => Some("S05".to_string()),
+        Ok(StopReason::Watchpoint { addr, access, .. }) => {
+            let name = match access {
+                WatchKind::Read => "rwatch",
+                _ => "watch",
+            };
+            Some(format!("T05{}:{:x};", name, *addr as usize * 2))
+        }
+        Ok(StopReason::Halted) => Some("W00".to_string()),
+        // tells gdb that there is no more execution history to go back through
+        Ok(StopReason::StartOfHistory) => Some("T05replaylog:begin;".to_string()),
+        Err(_) => Some("S04".to_string()),
+    }
+}
+
+fn interrupt_requested(stream: &mut TcpStream) -> io::Result<bool> {
+    // gdb sends a single 0x03 byte when the user presses ctrl-c
+    stream.set_nonblocking(true)?;
+    let mut byte = [0u8];
+    let res = match stream.peek(&mut byte) {
+        Ok(n) if n > 0 && byte[0] == 0x03 => {
+            stream.read_exact(&mut byte)?;
+            Ok(true)
+        }
+        Ok(_) => Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    };
+    stream.set_nonblocking(false)?;
+    res
+}
+
+fn packet_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn encode_word(value: u16) -> String {
+    let bytes = value.to_le_bytes();
+    format!("{:0>2x}{:0>2x}", bytes[0], bytes[1])
+}
+
+fn is_hex(s: &str) -> bool {
+    // checked before slicing, as packets can hold any bytes
+    s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn decode_word(s: &str) -> Option<u16> {
+    if s.len() != 4 || !is_hex(s) {
+        return None;
+    }
+    let lo = u8::from_str_radix(&s[0..2], 16).ok()?;
+    let hi = u8::from_str_radix(&s[2..4], 16).ok()?;
+    Some(u16::from_le_bytes([lo, hi]))
+}
+
+fn parse_addr_len(s: &str, separator: char) -> Option<(usize, usize)> {
+    let (addr, len) = s.split_once(separator)?;
+    Some((
+        usize::from_str_radix(addr, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instr_repr::{Operand, Verb};
+    use std::thread;
+
+    fn send(stream: &mut TcpStream, data: &[u8]) -> String {
+        // sends a packet and returns the reply, acking it like gdb does
+        let mut packet = vec![b'$'];
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(format!("#{:0>2x}", packet_checksum(data)).as_bytes());
+        stream.write_all(&packet).unwrap();
+
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'+');
+        stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'$');
+        let mut reply = Vec::new();
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+        let mut checksum = [0u8; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap();
+        assert_eq!(checksum, packet_checksum(&reply));
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+
+    #[test]
+    fn scripted_session() {
+        let program = [
+            Verb::Mov(Operand::Reg(Reg::R1), Operand::Imm(5)),
+            Verb::Add(Operand::Reg(Reg::R1), Operand::Imm(1)),
+            Verb::Nop,
+            Verb::Halt,
+        ];
+        let cpu_emulator = CpuEmu::new(program.iter().map(Verb::to_bytes).collect());
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // the emulator stays on this thread, gdb's side runs on another
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            assert!(send(&mut stream, b"qSupported:swbreak+").contains("PacketSize=1000"));
+            assert_eq!(send(&mut stream, b"g"), "0000".repeat(NUM_REGS));
+            assert_eq!(send(&mut stream, b"M4c0,2:3412"), "OK");
+            assert_eq!(send(&mut stream, b"m4c0,3"), "341200");
+            assert_eq!(send(&mut stream, b"Z0,2,3"), "OK");
+            assert_eq!(send(&mut stream, b"s"), "S05");
+            assert_eq!(send(&mut stream, b"p10"), "0100");
+            assert_eq!(send(&mut stream, b"c"), "S05");
+            assert_eq!(send(&mut stream, b"p10"), "0200");
+            assert_eq!(&send(&mut stream, b"g")[4..8], "0600");
+
+            // malformed packets are refused instead of crashing the stub
+            assert_eq!(send(&mut stream, b"mffffffffffffffff,2"), "E01");
+            assert_eq!(send(&mut stream, b"M1,ffffffffffffffff:00"), "E01");
+            assert_eq!(send(&mut stream, "M0,2:\u{e9}0".as_bytes()), "E01");
+            let mut regs = "\u{e9}".repeat(NUM_REGS * 2).into_bytes();
+            regs.insert(0, b'G');
+            assert_eq!(send(&mut stream, &regs), "E01");
+            assert_eq!(send(&mut stream, "P1=0\u{e9}0".as_bytes()), "E01");
+
+            // packets with bad checksums are nacked until one arrives intact
+            let bad = b"$g#00".repeat(10_000);
+            stream.write_all(&bad).unwrap();
+            let mut nacks = vec![0u8; 10_000];
+            stream.read_exact(&mut nacks).unwrap();
+            assert!(nacks.iter().all(|&byte| byte == b'-'));
+            assert_eq!(send(&mut stream, b"p10"), "0200");
+
+            assert_eq!(send(&mut stream, b"z0,2,3"), "OK");
+            assert_eq!(send(&mut stream, b"c"), "W00");
+            assert_eq!(send(&mut stream, b"D"), "OK");
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        GdbStub::new(cpu_emulator).serve(stream).unwrap();
+        client.join().unwrap();
+    }
+}
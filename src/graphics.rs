@@ -1,8 +1,85 @@
 use macroquad::prelude::*;
 
-pub async fn draw_monitor(x: f32, y: f32, w: f32, h: f32, buf: &[i16]) {
-    let pixel_width = w / 160.0;
-    let pixel_height = h / 120.0;
+use crate::fault::EmuFault;
+use crate::seven_segment::DECIMAL_POINT;
+
+pub const SCREEN_WIDTH: u16 = 160;
+pub const SCREEN_HEIGHT: u16 = 120;
+
+// the VGA framebuffer as a texture, scaled up with nearest-neighbour filtering. It is only uploaded
+// again when the framebuffer has changed since the last frame.
+pub struct Monitor {
+    image: Image,
+    texture: Texture2D,
+    // the framebuffer words the texture shows
+    shown: Vec<i16>,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        let image = Image::gen_image_color(SCREEN_WIDTH, SCREEN_HEIGHT, BLACK);
+        let texture = Texture2D::from_image(&image);
+        texture.set_filter(FilterMode::Nearest);
+        Monitor {
+            image,
+            texture,
+            shown: vec![0; SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize / 16],
+        }
+    }
+
+    pub fn update(&mut self, buf: &[i16]) -> bool {
+        // returns whether the texture had to be uploaded
+        if !draw_changed_words(buf, &mut self.shown, &mut self.image.bytes) {
+            return false;
+        }
+        self.texture.update(&self.image);
+        true
+    }
+
+    pub fn draw(&self, x: f32, y: f32, w: f32, h: f32) {
+        draw_texture_ex(
+            &self.texture,
+            x,
+            y,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(vec2(w, h)),
+                ..Default::default()
+            },
+        );
+    }
+}
+
+fn draw_changed_words(buf: &[i16], shown: &mut [i16], rgba: &mut [u8]) -> bool {
+    // redraws the pixels of the framebuffer words that differ from the shown ones, and returns
+    // whether there were any
+    if shown == buf {
+        return false;
+    }
+    for (i, (word, shown)) in buf.iter().zip(shown.iter_mut()).enumerate() {
+        if word == shown {
+            continue;
+        }
+        // words run along the rows, with the leftmost pixel in the top bit
+        for pixel in 0..16 {
+            let value = if (word >> (15 - pixel)) & 0x01 == 1 {
+                255
+            } else {
+                0
+            };
+            let offset = (i * 16 + pixel) * 4;
+            rgba[offset..offset + 3].fill(value);
+        }
+        *shown = *word;
+    }
+    true
+}
+
+pub fn draw_monitor_rects(x: f32, y: f32, w: f32, h: f32, buf: &[i16]) {
+    // a rectangle per pixel, as the window used to draw the monitor. Only kept to compare against
+    // with --bench-frames.
+    let pixel_width = w / SCREEN_WIDTH as f32;
+    let pixel_height = h / SCREEN_HEIGHT as f32;
 
     for row in 0..120 {
         for col in 0..10 {
@@ -35,6 +112,40 @@ pub async fn draw_leds(x: f32, y: f32, led_values: i16) {
     }
 }
 
+pub async fn draw_seven_segment(x: f32, y: f32, digits: &[u8; 4]) {
+    // segments a-g as (x, y, w, h) within a 20x36 digit, see seven_segment.rs
+    let segments: [(f32, f32, f32, f32); 7] = [
+        (3.0, 0.0, 14.0, 3.0),
+        (17.0, 3.0, 3.0, 14.0),
+        (17.0, 19.0, 3.0, 14.0),
+        (3.0, 33.0, 14.0, 3.0),
+        (0.0, 19.0, 3.0, 14.0),
+        (0.0, 3.0, 3.0, 14.0),
+        (3.0, 16.5, 14.0, 3.0),
+    ];
+    let digit_spacing = 30.0;
+
+    draw_rectangle(x - 6.0, y - 6.0, 4.0 * digit_spacing + 6.0, 48.0, BLACK);
+    // digit 3 is the leftmost one
+    for (pos, pattern) in digits.iter().rev().enumerate() {
+        let digit_x = x + pos as f32 * digit_spacing;
+        for (bit, (sx, sy, w, h)) in segments.iter().enumerate() {
+            let color = if pattern & (1 << bit) != 0 {
+                RED
+            } else {
+                DARKGRAY
+            };
+            draw_rectangle(digit_x + sx, y + sy, *w, *h, color);
+        }
+        let color = if pattern & DECIMAL_POINT != 0 {
+            RED
+        } else {
+            DARKGRAY
+        };
+        draw_rectangle(digit_x + 22.0, y + 33.0, 3.0, 3.0, color);
+    }
+}
+
 pub async fn draw_switches(x: f32, y: f32, switch_states: &mut i16) {
     let led_spacing = 30.0;
 
@@ -81,3 +192,51 @@ pub async fn get_curr_button_states() -> i16 {
 
     states
 }
+
+pub fn draw_fault(x: f32, y: f32, fault: &EmuFault) {
+    let location = match fault.instr() {
+        Some(verb) => format!(
+            "FAULT at IP 0x{:X}: {}",
+            fault.ip(),
+            verb.to_string().trim_end()
+        ),
+        None => format!("FAULT at IP 0x{:X}", fault.ip()),
+    };
+    draw_text(&location, x, y, 20.0, RED);
+    draw_text(&fault.message(), x, y + 18.0, 18.0, RED);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changed_words_are_redrawn() {
+        let words = SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize / 16;
+        let mut shown = vec![0; words];
+        // bytes that were never written keep this value
+        let mut rgba = vec![7; words * 16 * 4];
+        assert!(!draw_changed_words(&vec![0; words], &mut shown, &mut rgba));
+        assert!(rgba.iter().all(|&byte| byte == 7));
+
+        // the second word of row 3
+        let mut buf = vec![0; words];
+        buf[3 * 10 + 1] = 0x8001u16 as i16;
+        assert!(draw_changed_words(&buf, &mut shown, &mut rgba));
+        assert_eq!(shown, buf);
+        let start = (3 * 160 + 16) * 4;
+        for (i, pixel) in rgba.chunks(4).enumerate() {
+            let expected = match i.checked_sub(start / 4) {
+                Some(0) | Some(15) => [255, 255, 255, 7],
+                Some(1..=14) => [0, 0, 0, 7],
+                _ => [7; 4],
+            };
+            assert_eq!(pixel, expected, "pixel {}", i);
+        }
+
+        // nothing changed since
+        let drawn = rgba.clone();
+        assert!(!draw_changed_words(&buf, &mut shown, &mut rgba));
+        assert_eq!(rgba, drawn);
+    }
+}
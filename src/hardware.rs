@@ -0,0 +1,59 @@
+use clap::ValueEnum;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum HardwareProfile {
+    // the memories cpu_unit.v declares: instr_mem [0:1023] and program_mem [0:1300]
+    #[default]
+    Basys3,
+    // all 65536 data words, and as many instructions as the program has
+    Unlimited,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum OutOfRange {
+    // stop with a fault, like the debuggers and headless runs report any other fault
+    #[default]
+    Fault,
+    // print a warning the first time each address is accessed, and carry on
+    Warn,
+}
+
+impl HardwareProfile {
+    pub fn instr_words(&self) -> Option<usize> {
+        match self {
+            HardwareProfile::Basys3 => Some(1024),
+            HardwareProfile::Unlimited => None,
+        }
+    }
+
+    pub fn data_words(&self) -> Option<usize> {
+        match self {
+            HardwareProfile::Basys3 => Some(1301),
+            HardwareProfile::Unlimited => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HardwareProfile::Basys3 => "basys3",
+            HardwareProfile::Unlimited => "unlimited",
+        }
+    }
+
+    pub fn check_program_size(&self, code: &[[u8; 3]]) -> Result<(), String> {
+        match self.instr_words() {
+            Some(max) if code.len() > max => Err(format!(
+                "program has {} instruction words, but the {} only has room for {}",
+                code.len(),
+                self.name(),
+                max
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn has_data_addr(&self, addr: u16) -> bool {
+        self.data_words()
+            .is_none_or(|words| (addr as usize) < words)
+    }
+}
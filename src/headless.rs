@@ -0,0 +1,130 @@
+use crate::capture::Capture;
+use crate::emu::{CpuEmu, StopReason, WatchKind};
+use crate::fault::EmuFault;
+
+// exit code 1 is used for assembler errors
+pub const EXIT_HALTED: i32 = 0;
+pub const EXIT_BUDGET_EXCEEDED: i32 = 2;
+pub const EXIT_FAULTED: i32 = 3;
+pub const EXIT_STOPPED: i32 = 4;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum HeadlessOutcome {
+    Halted,
+    BudgetExceeded,
+    Faulted(EmuFault),
+    // hit a breakpoint or watchpoint
+    Stopped(StopReason),
+}
+
+impl HeadlessOutcome {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            HeadlessOutcome::Halted => EXIT_HALTED,
+            HeadlessOutcome::BudgetExceeded => EXIT_BUDGET_EXCEEDED,
+            HeadlessOutcome::Faulted(_) => EXIT_FAULTED,
+            HeadlessOutcome::Stopped(_) => EXIT_STOPPED,
+        }
+    }
+}
+
+pub fn run_headless(
+    cpu_emulator: &mut CpuEmu,
+    max_instructions: Option<u64>,
+    capture: &mut Capture,
+) -> Result<HeadlessOutcome, String> {
+    // the run is split up at the instruction counts where the display is captured. Fails if a
+    // capture could not be saved.
+    let end = max_instructions.unwrap_or(u64::MAX);
+    loop {
+        capture.take_due(cpu_emulator)?;
+        let executed = cpu_emulator.get_instructions_executed();
+        if executed >= end {
+            return Ok(HeadlessOutcome::BudgetExceeded);
+        }
+        let until = capture.get_next_count().map_or(end, |count| count.min(end));
+
+        return Ok(match cpu_emulator.run(until - executed) {
+            Ok(StopReason::Halted) => HeadlessOutcome::Halted,
+            Ok(StopReason::BudgetExhausted) => continue,
+            Ok(stop) => HeadlessOutcome::Stopped(stop),
+            Err(fault) => HeadlessOutcome::Faulted(fault),
+        });
+    }
+}
+
+pub fn print_outcome(cpu_emulator: &CpuEmu, outcome: &HeadlessOutcome) {
+    let executed = cpu_emulator.get_instructions_executed();
+    match outcome {
+        HeadlessOutcome::Halted => {
+            println!("halted after {} instructions.", executed)
+        }
+        HeadlessOutcome::BudgetExceeded => {
+            println!(
+                "instruction budget exceeded after {} instructions (IP 0x{:X}).",
+                executed,
+                cpu_emulator.get_ip()
+            )
+        }
+        HeadlessOutcome::Faulted(fault) => {
+            println!("faulted after {} instructions: {}", executed, fault)
+        }
+        HeadlessOutcome::Stopped(StopReason::Breakpoint(addr)) => {
+            println!(
+                "stopped at breakpoint 0x{:X} after {} instructions.",
+                addr, executed
+            )
+        }
+        HeadlessOutcome::Stopped(StopReason::Watchpoint { addr, access, ip }) => {
+            let access = match access {
+                WatchKind::Read => "read",
+                _ => "write",
+            };
+            println!(
+                "stopped by watchpoint: {} of 0x{:X} by the instruction at 0x{:X}, after {} instructions.",
+                access, addr, ip, executed
+            )
+        }
+        HeadlessOutcome::Stopped(stop) => println!("stopped: {:?}", stop),
+    }
+    println!(
+        "hardware time: {} cycles ({:.6} s)",
+        cpu_emulator.get_elapsed_cycles(),
+        cpu_emulator.get_hardware_time().as_secs_f64()
+    );
+}
+
+pub fn dump_regs(cpu_emulator: &CpuEmu) {
+    println!("IP:  0x{:0>4X}", cpu_emulator.get_ip());
+    for (id, value) in cpu_emulator.get_regs().iter().enumerate() {
+        println!("R{:<2} 0x{:0>4X} ({})", id, *value as u16, value);
+    }
+}
+
+pub fn dump_mem(cpu_emulator: &CpuEmu, start: u16, end: u16) {
+    let mem = cpu_emulator.get_mem();
+    for addr in start..=end {
+        let value = mem[addr as usize];
+        println!("0x{:0>4X}: 0x{:0>4X} ({})", addr, value as u16, value);
+    }
+}
+
+pub fn parse_mem_range(s: &str) -> Result<(u16, u16), String> {
+    // START:END, both inclusive, e.g. 0x4c0:0x4c6
+    let (start, end) = s
+        .split_once(':')
+        .ok_or(format!("expected a range like 0x4c0:0x4c6, found `{}`", s))?;
+    let parse = |v: &str| parse_u16(v).ok_or(format!("invalid address `{}`", v));
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start > end {
+        return Err(format!("range start 0x{:X} is after its end 0x{:X}", start, end));
+    }
+    Ok((start, end))
+}
+
+fn parse_u16(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x") {
+        Some(hex_digits) => u16::from_str_radix(hex_digits, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
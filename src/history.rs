@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+
+use crate::emu::CallFrame;
+use crate::instr_repr::Reg;
+
+// how many executed instructions can be undone
+pub const HISTORY_LIMIT: usize = 1_000_000;
+
+// a full copy of the machine is kept this often, so that going back a long way
+// does not have to undo every instruction in between
+pub const SNAPSHOT_INTERVAL: u64 = 100_000;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CallStackChange {
+    None,
+    Pushed,
+    Popped(CallFrame),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct UndoEntry {
+    // instruction count, IP and interrupt enable from before the instruction ran
+    pub count: u64,
+    pub ip: u16,
+    pub interrupts_enabled: bool,
+    // the register that was written, with its old value
+    pub reg: Option<(Reg, i16)>,
+    // the memory word that was written, whose old value is among the DeviceWrites of the step
+    pub mem_written: Option<u16>,
+    pub mem_read: Option<u16>,
+    pub call_stack: CallStackChange,
+    // position in the input script, whose events the step may have applied
+    pub script_position: usize,
+    // bytes the UART had received, the step may have received one more
+    pub uart_received: usize,
+}
+
+// a memory word changed by an instruction or a device while or after it ran, see DeviceMem, or by
+// a scripted input before it
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DeviceWrite {
+    // the instruction it happened with, as UndoEntry::count
+    pub count: u64,
+    pub addr: u16,
+    pub old: i16,
+}
+
+pub struct Snapshot {
+    pub count: u64,
+    pub ip: u16,
+    pub halted: bool,
+    pub interrupts_enabled: bool,
+    pub regs: [i16; 16],
+    pub mem: Vec<i16>,
+    pub call_stack: Vec<CallFrame>,
+    pub script_position: usize,
+    pub uart_received: usize,
+}
+
+#[derive(Default)]
+pub struct History {
+    undo_log: VecDeque<UndoEntry>,
+    // kept apart from the undo log since most instructions have none
+    device_writes: VecDeque<DeviceWrite>,
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl History {
+    pub fn push(&mut self, entry: UndoEntry) {
+        self.undo_log.push_back(entry);
+        if self.undo_log.len() > HISTORY_LIMIT {
+            self.undo_log.pop_front();
+            // a snapshot is only useful while the instructions after it can still be undone
+            let oldest = self.oldest_count().unwrap_or(u64::MAX);
+            while matches!(self.device_writes.front(), Some(w) if w.count < oldest) {
+                self.device_writes.pop_front();
+            }
+            while matches!(self.snapshots.front(), Some(s) if s.count < oldest) {
+                self.snapshots.pop_front();
+            }
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<UndoEntry> {
+        let entry = self.undo_log.pop_back()?;
+        while matches!(self.snapshots.back(), Some(s) if s.count > entry.count) {
+            self.snapshots.pop_back();
+        }
+        Some(entry)
+    }
+
+    pub fn push_device_write(&mut self, write: DeviceWrite) {
+        self.device_writes.push_back(write);
+    }
+
+    pub fn pop_device_write(&mut self, count: u64) -> Option<DeviceWrite> {
+        // the device writes after instruction `count`, latest first
+        match self.device_writes.back() {
+            Some(w) if w.count == count => self.device_writes.pop_back(),
+            _ => None,
+        }
+    }
+
+    pub fn wants_snapshot(&self, count: u64) -> bool {
+        count.is_multiple_of(SNAPSHOT_INTERVAL)
+            && self.snapshots.back().is_none_or(|s| s.count < count)
+    }
+
+    pub fn push_snapshot(&mut self, snapshot: Snapshot) {
+        self.snapshots.push_back(snapshot);
+    }
+
+    pub fn oldest_count(&self) -> Option<u64> {
+        self.undo_log.front().map(|entry| entry.count)
+    }
+
+    pub fn snapshot_at_or_before(&self, count: u64) -> Option<&Snapshot> {
+        self.snapshots.iter().rev().find(|s| s.count <= count)
+    }
+
+    pub fn truncate_to(&mut self, count: u64) {
+        // forgets everything that happened from instruction `count` on, e.g. after restoring a snapshot
+        while matches!(self.undo_log.back(), Some(entry) if entry.count >= count) {
+            self.undo_log.pop_back();
+        }
+        while matches!(self.device_writes.back(), Some(w) if w.count >= count) {
+            self.device_writes.pop_back();
+        }
+        while matches!(self.snapshots.back(), Some(s) if s.count > count) {
+            self.snapshots.pop_back();
+        }
+    }
+
+    pub fn last_write(&self, addr: u16) -> Option<u64> {
+        self.undo_log
+            .iter()
+            .rev()
+            .find(|entry| entry.mem_written == Some(addr))
+            .map(|entry| entry.count)
+    }
+}
@@ -0,0 +1,207 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+
+// the keys that drive the push buttons, with the bits get_curr_button_states sets for them
+const BUTTON_KEYS: [(&str, i16); 5] = [("W", 16), ("X", 8), ("A", 4), ("D", 2), ("S", 1)];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InputAction {
+    Press(i16),
+    Release(i16),
+    // set every button or switch at once
+    Buttons(i16),
+    Switches(i16),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct InputEvent {
+    // number of instructions executed before the event takes effect
+    pub at: u64,
+    pub action: InputAction,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InputScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for InputScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+pub struct InputScript {
+    events: Vec<InputEvent>,
+    next: usize,
+}
+
+impl InputScript {
+    pub fn new(mut events: Vec<InputEvent>) -> Self {
+        // events at the same instruction count keep their order
+        events.sort_by_key(|event| event.at);
+        InputScript { events, next: 0 }
+    }
+
+    pub fn load(filepath: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(filepath)
+            .map_err(|_| format!("could not open file: {}", filepath))?;
+        Self::parse(&contents).map_err(|e| format!("{}: {}", filepath, e))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, InputScriptError> {
+        // statements look like `at 12000: press A` and are separated by newlines or `;`.
+        // `#` starts a comment.
+        let mut events = Vec::new();
+        for (line_idx, line) in contents.lines().enumerate() {
+            let code = match line.find('#') {
+                Some(comment_start) => &line[..comment_start],
+                None => line,
+            };
+            for statement in code.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+                let event = parse_statement(statement).map_err(|message| InputScriptError {
+                    line: line_idx + 1,
+                    message,
+                })?;
+                events.push(event);
+            }
+        }
+        Ok(Self::new(events))
+    }
+
+    pub fn get_position(&self) -> usize {
+        // how many events have been applied, which reverse debugging goes back to
+        self.next
+    }
+
+    pub fn set_position(&mut self, position: usize) {
+        self.next = position;
+    }
+
+    pub fn apply(&mut self, count: u64, switches: &mut i16, buttons: &mut i16) -> bool {
+        // applies every event that is due after `count` instructions. Returns whether any was.
+        let start = self.next;
+        while let Some(event) = self.events.get(self.next) {
+            if event.at > count {
+                break;
+            }
+            match event.action {
+                InputAction::Press(mask) => *buttons |= mask,
+                InputAction::Release(mask) => *buttons &= !mask,
+                InputAction::Buttons(value) => *buttons = value,
+                InputAction::Switches(value) => *switches = value,
+            }
+            self.next += 1;
+        }
+        self.next != start
+    }
+}
+
+fn parse_statement(statement: &str) -> Result<InputEvent, String> {
+    let rest = statement
+        .strip_prefix("at ")
+        .ok_or(format!("expected `at N: ...`, found `{}`", statement))?;
+    let (at, action) = rest.split_once(':').ok_or(format!(
+        "missing `:` after the instruction count in `{}`",
+        statement
+    ))?;
+    let at = parse_number(at.trim()).ok_or(format!("invalid instruction count `{}`", at.trim()))?;
+
+    let mut words = action.split_whitespace();
+    let verb = words.next().unwrap_or("");
+    let args: Vec<&str> = words.collect();
+    if args.is_empty() {
+        return Err(format!("`{}` needs an argument", verb));
+    }
+    let action = match verb {
+        "press" => InputAction::Press(parse_keys(&args)?),
+        "release" => InputAction::Release(parse_keys(&args)?),
+        "btn" | "sw" => {
+            let value = match args[..] {
+                [value] => parse_number(value)
+                    .filter(|v| *v <= 0xFFFF)
+                    .ok_or(format!("invalid value `{}`", value))? as u16
+                    as i16,
+                _ => return Err(format!("`{}` takes a single value", verb)),
+            };
+            if verb == "btn" {
+                InputAction::Buttons(value)
+            } else {
+                InputAction::Switches(value)
+            }
+        }
+        _ => return Err(format!("unknown input action `{}`", verb)),
+    };
+    Ok(InputEvent { at, action })
+}
+
+fn parse_keys(keys: &[&str]) -> Result<i16, String> {
+    let mut mask = 0;
+    for key in keys {
+        let (_, bit) = BUTTON_KEYS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .ok_or(format!(
+                "unknown button `{}`, expected one of W X A D S",
+                key
+            ))?;
+        mask |= bit;
+    }
+    Ok(mask)
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse::<u64>().ok(),
+    }
+}
+
+pub struct InputRecorder {
+    out: File,
+    switches: i16,
+    buttons: i16,
+}
+
+impl InputRecorder {
+    pub fn create(filepath: &str, switches: i16, buttons: i16) -> Result<Self, String> {
+        let mut out = File::create(filepath)
+            .map_err(|e| format!("could not create input recording {}: {}", filepath, e))?;
+        writeln!(out, "# replay with --input-script {}", filepath)
+            .map_err(|e| format!("could not write input recording {}: {}", filepath, e))?;
+        Ok(InputRecorder {
+            out,
+            switches,
+            buttons,
+        })
+    }
+
+    pub fn record(&mut self, count: u64, switches: i16, buttons: i16) {
+        // writes down whatever changed since the last call, as a replayable statement
+        let mut actions = Vec::new();
+        for (name, bit) in BUTTON_KEYS {
+            let (was_down, is_down) = (self.buttons & bit != 0, buttons & bit != 0);
+            if is_down && !was_down {
+                actions.push(format!("press {}", name));
+            } else if was_down && !is_down {
+                actions.push(format!("release {}", name));
+            }
+        }
+        if switches != self.switches {
+            actions.push(format!("sw 0x{:0>4X}", switches as u16));
+        }
+        self.switches = switches;
+        self.buttons = buttons;
+
+        if actions.is_empty() {
+            return;
+        }
+        let statements: Vec<String> = actions
+            .iter()
+            .map(|action| format!("at {}: {}", count, action))
+            .collect();
+        writeln!(self.out, "{}", statements.join("; ")).expect("error writing input recording");
+    }
+}
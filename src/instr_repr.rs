@@ -19,6 +19,10 @@ pub enum Verb {
     Call(Operand),
     Ret,
 
+    Ei,
+    Di,
+    Iret,
+
     Dbg(Operand),
     DbgRegs,
     Nop,
@@ -71,7 +75,29 @@ impl Operand {
 }
 
 impl Reg {
-    pub fn to_id(&self) -> u8 {
+    pub fn from_id(id: u8) -> Option<Reg> {
+        match id {
+            0 => Some(Reg::R0),
+            1 => Some(Reg::R1),
+            2 => Some(Reg::R2),
+            3 => Some(Reg::R3),
+            4 => Some(Reg::R4),
+            5 => Some(Reg::R5),
+            6 => Some(Reg::R6),
+            7 => Some(Reg::R7),
+            8 => Some(Reg::R8),
+            9 => Some(Reg::R9),
+            10 => Some(Reg::R10),
+            11 => Some(Reg::R11),
+            12 => Some(Reg::R12),
+            13 => Some(Reg::R13),
+            14 => Some(Reg::R14),
+            15 => Some(Reg::R15),
+            _ => None,
+        }
+    }
+
+    pub fn to_id(self) -> u8 {
         match self {
             Reg::R0 => 0,
             Reg::R1 => 1,
@@ -103,6 +129,29 @@ impl Reg {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DecodeError {
+    UnknownOpcode([u8; 3]),
+    NonZeroPadding([u8; 3]),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode(b) => write!(
+                f,
+                "unknown opcode in instruction word {:0>2X}_{:0>2X}_{:0>2X}",
+                b[0], b[1], b[2]
+            ),
+            DecodeError::NonZeroPadding(b) => write!(
+                f,
+                "unused bits are set in instruction word {:0>2X}_{:0>2X}_{:0>2X}",
+                b[0], b[1], b[2]
+            ),
+        }
+    }
+}
+
 impl fmt::Display for Reg {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "R{}", self.to_id())
@@ -142,6 +191,10 @@ impl fmt::Display for Verb {
 
             Verb::Call(o1) => write!(f, "call {}", o1),
             Verb::Ret => write!(f, "ret"),
+
+            Verb::Ei => write!(f, "ei"),
+            Verb::Di => write!(f, "di"),
+            Verb::Iret => write!(f, "iret"),
         }
     }
 }
@@ -287,10 +340,92 @@ impl Verb {
                 res[1] = 0xFF;
                 res[2] = 0xF0;
             }
+            Verb::Ei => {
+                res[0] = 0xE5;
+            }
+            Verb::Di => {
+                res[0] = 0xE6;
+            }
+            Verb::Iret => {
+                res[0] = 0xFF;
+                res[1] = 0xFF;
+                res[2] = 0xF1;
+            }
         }
         res
     }
 
+    pub fn from_bytes(bytes: [u8; 3]) -> Result<Verb, DecodeError> {
+        // inverse of to_bytes. Only exact encodings are accepted, so that
+        // decoding and re-encoding an instruction word always gives back the same bits.
+        let imm = u16::from_be_bytes([bytes[1], bytes[2]]);
+        let nibble_reg = |n: u8| Operand::Reg(Reg::from_id(n & 0x0F).unwrap());
+        let ra = nibble_reg(bytes[0]);
+        let rb = nibble_reg(bytes[2] >> 4);
+        let rc = nibble_reg(bytes[2]);
+
+        let unknown = Err(DecodeError::UnknownOpcode(bytes));
+        let padded = Err(DecodeError::NonZeroPadding(bytes));
+
+        let verb = match bytes[0] >> 4 {
+            0x0 => match bytes {
+                [0x00, 0x00, 0x00] => Verb::Nop,
+                _ => return unknown,
+            },
+            0x1 => Verb::Mov(ra, Operand::Imm(imm)),
+            0x2 => Verb::Mov(ra, Operand::MemAtImm(imm)),
+            0x3 => Verb::Mov(Operand::MemAtImm(imm), ra),
+            0x4 => Verb::Jz(Operand::Imm(imm), ra),
+            0x5 => Verb::Jnz(Operand::Imm(imm), ra),
+
+            0xA => Verb::Add(ra, Operand::Imm(imm)),
+            0xB => Verb::Sub(ra, Operand::Imm(imm)),
+            0xC => Verb::And(ra, Operand::Imm(imm)),
+            0xD => Verb::Or(ra, Operand::Imm(imm)),
+
+            0xE => match bytes[0] {
+                0xE0 => Verb::Dbg(Operand::Imm(imm)),
+                0xE1 if imm == 0 => Verb::DbgRegs,
+                0xE1 => return padded,
+                0xE3 => Verb::Jmp(Operand::Imm(imm)),
+                0xE4 => Verb::Call(Operand::Imm(imm)),
+                0xE5 if imm == 0 => Verb::Ei,
+                0xE6 if imm == 0 => Verb::Di,
+                0xE5 | 0xE6 => return padded,
+                _ => return unknown,
+            },
+
+            0xF => match (bytes[0], bytes[1]) {
+                (0xF0, 0x00) => Verb::Mov(rb, rc),
+                (0xF0, 0x01) => Verb::Mov(rb, Operand::MemAtReg(rc.to_reg())),
+                (0xF0, 0x02) => Verb::Mov(Operand::MemAtReg(rb.to_reg()), rc),
+
+                (0xF0, 0x20) => Verb::Add(rb, rc),
+                (0xF0, 0x21) => Verb::Sub(rb, rc),
+                (0xF0, 0x22) => Verb::And(rb, rc),
+                (0xF0, 0x23) => Verb::Or(rb, rc),
+                (0xF0, 0x24) if bytes[2] & 0x0F == 0 => Verb::Not(rb),
+                (0xF0, 0x24) => return padded,
+
+                (0xF0, 0x30) => Verb::Shl(rb, Operand::Imm((bytes[2] & 0x0F) as u16)),
+                (0xF0, 0x31) => Verb::Shl(rb, rc),
+                (0xF0, 0x32) => Verb::Shr(rb, Operand::Imm((bytes[2] & 0x0F) as u16)),
+                (0xF0, 0x33) => Verb::Shr(rb, rc),
+
+                (0xFF, 0xFF) => match bytes[2] {
+                    0xFF => Verb::Halt,
+                    0xF0 => Verb::Ret,
+                    0xF1 => Verb::Iret,
+                    _ => return unknown,
+                },
+                _ => return unknown,
+            },
+
+            _ => return unknown,
+        };
+        Ok(verb)
+    }
+
     pub fn as_hex_file_line(&self) -> String {
         let bytes = self.to_bytes();
 
@@ -307,3 +442,104 @@ pub fn write_imm_to_byte_lower(imm: u16, b: &mut u8) {
     *b &= 0xF0;
     *b |= imm.to_be_bytes()[1] & 0x0F;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_shape(ra: Reg, rb: Reg, imm: u16) -> Vec<Verb> {
+        // one of each verb and operand combination the encoder supports
+        let (a, b) = (Operand::Reg(ra), Operand::Reg(rb));
+        let imm_op = Operand::Imm(imm);
+        vec![
+            Verb::Mov(a.clone(), imm_op.clone()),
+            Verb::Mov(a.clone(), Operand::MemAtImm(imm)),
+            Verb::Mov(Operand::MemAtImm(imm), a.clone()),
+            Verb::Mov(a.clone(), b.clone()),
+            Verb::Mov(a.clone(), Operand::MemAtReg(rb)),
+            Verb::Mov(Operand::MemAtReg(ra), b.clone()),
+            Verb::Jmp(imm_op.clone()),
+            Verb::Jz(imm_op.clone(), a.clone()),
+            Verb::Jnz(imm_op.clone(), a.clone()),
+            Verb::Add(a.clone(), b.clone()),
+            Verb::Add(a.clone(), imm_op.clone()),
+            Verb::Sub(a.clone(), b.clone()),
+            Verb::Sub(a.clone(), imm_op.clone()),
+            Verb::And(a.clone(), b.clone()),
+            Verb::And(a.clone(), imm_op.clone()),
+            Verb::Or(a.clone(), b.clone()),
+            Verb::Or(a.clone(), imm_op.clone()),
+            Verb::Not(a.clone()),
+            Verb::Shl(a.clone(), b.clone()),
+            Verb::Shl(a.clone(), Operand::Imm(imm & 0x0F)),
+            Verb::Shr(a.clone(), b.clone()),
+            Verb::Shr(a.clone(), Operand::Imm(imm & 0x0F)),
+            Verb::Call(imm_op.clone()),
+            Verb::Ret,
+            Verb::Ei,
+            Verb::Di,
+            Verb::Iret,
+            Verb::Dbg(imm_op),
+            Verb::DbgRegs,
+            Verb::Nop,
+            Verb::Halt,
+        ]
+    }
+
+    #[test]
+    fn encoded_verbs_decode_to_themselves() {
+        let regs = [Reg::R0, Reg::R1, Reg::R7, Reg::R15];
+        for ra in regs {
+            for rb in regs {
+                for imm in [0, 1, 0x4B4, 0x7FFF, 0x8000, 0xFFFF] {
+                    for verb in every_shape(ra, rb, imm) {
+                        assert_eq!(Verb::from_bytes(verb.to_bytes()), Ok(verb.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decoded_words_encode_to_themselves() {
+        for word in 0..=0xFF_FFFFu32 {
+            let bytes = [(word >> 16) as u8, (word >> 8) as u8, word as u8];
+            if let Ok(verb) = Verb::from_bytes(bytes) {
+                assert_eq!(verb.to_bytes(), bytes, "{}", verb);
+            }
+        }
+    }
+
+    #[test]
+    fn padding_and_unknown_opcodes_are_rejected() {
+        let cases = [
+            (
+                [0xE1, 0x00, 0x01],
+                DecodeError::NonZeroPadding([0xE1, 0x00, 0x01]),
+            ),
+            (
+                [0xE5, 0x01, 0x00],
+                DecodeError::NonZeroPadding([0xE5, 0x01, 0x00]),
+            ),
+            (
+                [0xF0, 0x24, 0x13],
+                DecodeError::NonZeroPadding([0xF0, 0x24, 0x13]),
+            ),
+            (
+                [0x00, 0x00, 0x01],
+                DecodeError::UnknownOpcode([0x00, 0x00, 0x01]),
+            ),
+            (
+                [0x60, 0x00, 0x00],
+                DecodeError::UnknownOpcode([0x60, 0x00, 0x00]),
+            ),
+            (
+                [0xFF, 0xFF, 0xF2],
+                DecodeError::UnknownOpcode([0xFF, 0xFF, 0xF2]),
+            ),
+        ];
+        for (bytes, error) in cases {
+            assert_eq!(Verb::from_bytes(bytes), Err(error));
+        }
+    }
+}
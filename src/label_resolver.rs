@@ -1,20 +1,29 @@
 use std::collections::HashMap;
 
+use crate::assemble_error::{AssembleError, Span};
 use crate::instr_repr::{Operand, Verb};
+use crate::tokens::convert_str_to_imm;
 
-pub fn resolve_labels(instrs: &mut Vec<Verb>, label_map: &HashMap<String, u16>) {
-    for verb in instrs {
+pub fn resolve_labels(
+    instrs: &mut [Verb],
+    label_map: &HashMap<String, u16>,
+    spans: &[Span],
+) -> Result<(), Vec<AssembleError>> {
+    let mut errors = Vec::new();
+
+    for (verb, span) in instrs.iter_mut().zip(spans) {
         match verb {
             Verb::Jmp(operand)
             | Verb::Jz(operand, _)
             | Verb::Jnz(operand, _)
-            | Verb::Call(operand) => {
+            | Verb::Call(operand)
+            | Verb::Mov(_, operand) => {
                 if let Operand::Label(s) = operand {
                     let optional_addr = label_map.get(s);
                     if let Some(addr) = optional_addr {
                         *operand = Operand::Imm(*addr);
                     } else {
-                        panic!("unresolved label: {}", s);
+                        errors.push(AssembleError::new(format!("unresolved label: {}", s), *span));
                     }
                 }
             }
@@ -22,4 +31,22 @@ pub fn resolve_labels(instrs: &mut Vec<Verb>, label_map: &HashMap<String, u16>)
             _ => {}
         }
     }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+pub fn resolve_address(
+    s: &str,
+    label_map: &HashMap<String, u16>,
+    var_loc_map: &HashMap<String, u16>,
+) -> Option<u16> {
+    // a `.label`, a name from vars.locations, or a plain number
+    if s.starts_with('.') {
+        return label_map.get(s).copied();
+    }
+    convert_str_to_imm(s, var_loc_map)
 }
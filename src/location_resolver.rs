@@ -1,19 +1,14 @@
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::Read;
 
+use crate::assemble_error::{AssembleError, Span};
 use crate::source_cursor::SourceCodeCursor;
 use crate::tokens::{consume_rest_of_line, consume_whitespace, convert_str_to_imm};
 
-pub fn create_location_map(file: &str) -> HashMap<String, u16> {
-    let mut contents = String::new();
-    File::open(file)
-        .expect(&format!("could not open file: {}", file))
-        .read_to_string(&mut contents)
-        .expect(&format!("error reading file: {}", file));
+pub fn create_location_map(contents: String) -> Result<HashMap<String, u16>, Vec<AssembleError>> {
     let mut cursor = SourceCodeCursor::new(contents);
 
     let mut map = HashMap::new();
+    let mut errors = Vec::new();
 
     while cursor.peek().is_some() {
         consume_whitespace(&mut cursor);
@@ -26,15 +21,22 @@ pub fn create_location_map(file: &str) -> HashMap<String, u16> {
             continue;
         }
 
+        let (line, col) = (cursor.line(), cursor.col());
         let mut var_name = String::new();
         while cursor.peek().is_some() && !cursor.peek().unwrap().is_ascii_whitespace() {
             var_name.push(cursor.next().unwrap());
         }
-        if var_name == "" {
-            panic!("should not have empty variable name!")
+        if var_name.is_empty() {
+            errors.push(AssembleError::new(
+                "should not have empty variable name!",
+                Span::new(line, col, 1),
+            ));
+            consume_rest_of_line(&mut cursor);
+            continue;
         }
         consume_whitespace(&mut cursor);
 
+        let (operand_line, operand_col) = (cursor.line(), cursor.col());
         let mut operand_str: String = String::new();
         while cursor.peek().is_some() && (!cursor.peek().unwrap().is_ascii_whitespace()) {
             operand_str.push(cursor.next().unwrap());
@@ -43,11 +45,18 @@ pub fn create_location_map(file: &str) -> HashMap<String, u16> {
         if let Some(val) = convert_str_to_imm(&operand_str, &HashMap::new()) {
             map.insert(var_name, val);
         } else {
-            panic!("invalid operand string: {}", operand_str);
+            errors.push(AssembleError::new(
+                format!("invalid operand string for `{}`: `{}`", var_name, operand_str),
+                Span::new(operand_line, operand_col, operand_str.chars().count()),
+            ));
         }
 
         consume_rest_of_line(&mut cursor);
     }
 
-    map
+    if errors.is_empty() {
+        Ok(map)
+    } else {
+        Err(errors)
+    }
 }
@@ -0,0 +1,252 @@
+use std::fs::File;
+use std::io::{Read, Write};
+
+use crate::emu::CallFrame;
+
+// Snapshot file layout, all numbers little endian:
+//   magic "ASMSTATE", u16 version
+//   u32 program hash, u16 ip, u8 halted, u8 interrupts enabled, u64 instructions executed
+//   16 x i16 registers, i16 switches, i16 buttons
+//   65536 x i16 data memory
+//   u16 call stack depth, then (u16 call site, u16 target) per frame
+// Version 1 files, from before interrupts, have no interrupts enabled byte.
+const MAGIC: &[u8; 8] = b"ASMSTATE";
+const VERSION: u16 = 2;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MachineState {
+    // identifies the program the state was saved from, see program_hash
+    pub program_hash: u32,
+    pub ip: u16,
+    pub halted: bool,
+    pub interrupts_enabled: bool,
+    pub instructions_executed: u64,
+    pub regs: [i16; 16],
+    pub switches: i16,
+    pub buttons: i16,
+    pub mem: Vec<i16>,
+    pub call_stack: Vec<CallFrame>,
+}
+
+impl MachineState {
+    pub fn save(&self, filepath: &str) -> Result<(), String> {
+        File::create(filepath)
+            .and_then(|mut f| f.write_all(&self.to_bytes()))
+            .map_err(|e| format!("could not write state file {}: {}", filepath, e))
+    }
+
+    pub fn load(filepath: &str) -> Result<Self, String> {
+        let mut bytes = Vec::new();
+        File::open(filepath)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|e| format!("could not read state file {}: {}", filepath, e))?;
+        Self::from_bytes(&bytes).map_err(|e| format!("{}: {}", filepath, e))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(MAGIC.len() + 2 * self.mem.len() + 64);
+        res.extend_from_slice(MAGIC);
+        res.extend_from_slice(&VERSION.to_le_bytes());
+        res.extend_from_slice(&self.program_hash.to_le_bytes());
+        res.extend_from_slice(&self.ip.to_le_bytes());
+        res.push(self.halted as u8);
+        res.push(self.interrupts_enabled as u8);
+        res.extend_from_slice(&self.instructions_executed.to_le_bytes());
+        for reg in self.regs {
+            res.extend_from_slice(&reg.to_le_bytes());
+        }
+        res.extend_from_slice(&self.switches.to_le_bytes());
+        res.extend_from_slice(&self.buttons.to_le_bytes());
+        for word in &self.mem {
+            res.extend_from_slice(&word.to_le_bytes());
+        }
+        res.extend_from_slice(&(self.call_stack.len() as u16).to_le_bytes());
+        for frame in &self.call_stack {
+            res.extend_from_slice(&frame.call_site.to_le_bytes());
+            res.extend_from_slice(&frame.target.to_le_bytes());
+        }
+        res
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = ByteReader { bytes, pos: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err("not a machine state file".to_string());
+        }
+        let version = reader.u16()?;
+        if version != 1 && version != VERSION {
+            return Err(format!(
+                "unsupported state file version {} (expected {})",
+                version, VERSION
+            ));
+        }
+
+        let program_hash = reader.u32()?;
+        let ip = reader.u16()?;
+        let halted = reader.take(1)?[0] != 0;
+        let interrupts_enabled = version >= 2 && reader.take(1)?[0] != 0;
+        let instructions_executed = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+        let mut regs = [0; 16];
+        for reg in regs.iter_mut() {
+            *reg = reader.u16()? as i16;
+        }
+        let switches = reader.u16()? as i16;
+        let buttons = reader.u16()? as i16;
+        let mut mem = Vec::with_capacity(65536);
+        for _ in 0..65536 {
+            mem.push(reader.u16()? as i16);
+        }
+        let depth = reader.u16()?;
+        let mut call_stack = Vec::with_capacity(depth as usize);
+        for _ in 0..depth {
+            call_stack.push(CallFrame {
+                call_site: reader.u16()?,
+                target: reader.u16()?,
+            });
+        }
+        if reader.pos != bytes.len() {
+            return Err("unexpected data at the end of the state file".to_string());
+        }
+
+        Ok(MachineState {
+            program_hash,
+            ip,
+            halted,
+            interrupts_enabled,
+            instructions_executed,
+            regs,
+            switches,
+            buttons,
+            mem,
+            call_stack,
+        })
+    }
+}
+
+pub fn program_hash(instrs: &[[u8; 3]]) -> u32 {
+    // FNV-1a over the instruction words
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in instrs.iter().flatten() {
+        hash ^= *byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let res = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or("state file is truncated")?;
+        self.pos += len;
+        Ok(res)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> MachineState {
+        let mut mem = vec![0; 65536];
+        mem[0x4C0] = -5;
+        mem[0xFFFF] = 0x1234;
+        MachineState {
+            program_hash: program_hash(&[[0x11, 0x00, 0x05], [0xF0, 0x00, 0x00]]),
+            ip: 0x1C,
+            halted: false,
+            interrupts_enabled: true,
+            instructions_executed: 123_456_789_012,
+            regs: std::array::from_fn(|i| i as i16 * -3),
+            switches: 0x00F0,
+            buttons: 0x0004,
+            mem,
+            call_stack: vec![
+                CallFrame {
+                    call_site: 0x02,
+                    target: 0x10,
+                },
+                CallFrame {
+                    call_site: 0x12,
+                    target: 0x1A,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn saved_states_load_back() {
+        let path = std::env::temp_dir().join(format!("asm_emu_state_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let state = state();
+        state.save(path).unwrap();
+        assert_eq!(MachineState::load(path), Ok(state));
+        std::fs::remove_file(path).unwrap();
+
+        assert!(MachineState::load(path)
+            .unwrap_err()
+            .starts_with(&format!("could not read state file {}", path)));
+    }
+
+    #[test]
+    fn version_1_files_have_interrupts_disabled() {
+        // the same layout without the interrupts enabled byte after `halted`
+        let mut bytes = state().to_bytes();
+        bytes[8..10].copy_from_slice(&1u16.to_le_bytes());
+        bytes.remove(17);
+        let loaded = MachineState::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            loaded,
+            MachineState {
+                interrupts_enabled: false,
+                ..state()
+            }
+        );
+    }
+
+    #[test]
+    fn bad_files_are_rejected() {
+        let bytes = state().to_bytes();
+        for len in [0, 5, 10, 40, bytes.len() - 1] {
+            assert_eq!(
+                MachineState::from_bytes(&bytes[..len]),
+                Err("state file is truncated".to_string())
+            );
+        }
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(
+            MachineState::from_bytes(&bad_magic),
+            Err("not a machine state file".to_string())
+        );
+
+        let mut future = bytes.clone();
+        future[8..10].copy_from_slice(&3u16.to_le_bytes());
+        assert_eq!(
+            MachineState::from_bytes(&future),
+            Err("unsupported state file version 3 (expected 2)".to_string())
+        );
+
+        let mut trailing = bytes;
+        trailing.push(0);
+        assert_eq!(
+            MachineState::from_bytes(&trailing),
+            Err("unexpected data at the end of the state file".to_string())
+        );
+    }
+}
@@ -1,82 +1,635 @@
+mod asm_test;
+mod assemble_error;
+mod bus;
+mod capture;
+mod code_file;
+mod dap_server;
+mod debugger;
+mod devices;
+mod disassembler;
 mod emu;
+mod fault;
+mod frame_bench;
+mod gdb_stub;
 mod graphics;
+mod hardware;
+mod headless;
+mod history;
+mod input_script;
 mod instr_repr;
 mod label_resolver;
 mod location_resolver;
+mod machine_state;
+mod program;
+mod seven_segment;
 mod source_cursor;
+mod speed;
+mod timing;
 mod tokens;
+mod trace;
+mod uart;
 
-use std::fs::File;
-use std::io::Read;
-use std::io::Write;
+use std::sync::mpsc::Receiver;
+use std::time::Instant;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use emu::CpuEmu;
-use graphics::{draw_leds, draw_monitor, draw_switches, get_curr_button_states};
+use graphics::{
+    draw_fault, draw_leds, draw_monitor_rects, draw_seven_segment, draw_switches,
+    get_curr_button_states, Monitor, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+use macroquad::miniquad::conf::Platform;
 use macroquad::prelude::*;
-use tokens::get_tokens;
+use macroquad::window::Conf;
 
-use crate::label_resolver::resolve_labels;
-use crate::location_resolver::create_location_map;
+use crate::asm_test::{load_tests, run_tests, DEFAULT_BUDGET};
+use crate::capture::{parse_screenshot, Capture, FrameRecorder, WindowCapture};
+use crate::code_file::{parse_code_file, parse_raw_code};
+use crate::dap_server::DapServer;
+use crate::debugger::{parse_watchpoint, Debugger};
+use crate::devices::{parse_seed, seed_from_clock, DEFAULT_SEED, RNG_ADDR};
+use crate::disassembler::disassemble;
+use crate::frame_bench::FrameBench;
+use crate::gdb_stub::GdbStub;
+use crate::hardware::{HardwareProfile, OutOfRange};
+use crate::headless::{dump_mem, dump_regs, parse_mem_range, print_outcome, run_headless};
+use crate::input_script::{InputRecorder, InputScript};
+use crate::label_resolver::resolve_address;
+use crate::machine_state::MachineState;
+use crate::program::{read_file, Program, CODE_FILE_NAME};
+use crate::speed::{draw_speed_overlay, parse_speed, Speed, SpeedControl, UNLIMITED_FRAME_BUDGET};
+use crate::timing::{SyncMode, TimingModel, FRAME_DURATION, MAX_CATCH_UP};
+use crate::trace::{parse_trace_range, TraceFormat, Tracer};
+use crate::uart::{read_uart_input, spawn_stdin_reader};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Name of input file containing assembly
-    filename: String,
+    #[arg(required = true)]
+    filename: Option<String>,
+
+    /// Run without opening a window, until the program halts
+    #[arg(long)]
+    headless: bool,
+
+    /// Stop a headless run after this many instructions
+    #[arg(long, value_name = "N", requires = "headless")]
+    max_instructions: Option<u64>,
+
+    /// Print the registers at the end of a headless run
+    #[arg(long, requires = "headless")]
+    dump_regs: bool,
+
+    /// Stop a headless run before the instruction at this address or label
+    #[arg(long = "break", value_name = "ADDR", requires = "headless")]
+    breakpoints: Vec<String>,
+
+    /// Stop a headless run when this memory address is accessed, e.g. LED_ADDR:w
+    #[arg(long = "watch", value_name = "ADDR[:r|w|rw]", requires = "headless")]
+    watchpoints: Vec<String>,
+
+    /// Print a memory range at the end of a headless run, e.g. 0x4c0:0x4c6
+    #[arg(long, value_name = "START:END", value_parser = parse_mem_range, requires = "headless")]
+    dump_mem: Option<(u16, u16)>,
+
+    /// Start from a machine state saved with --save-state or the F5 key
+    #[arg(long, value_name = "FILE")]
+    load_state: Option<String>,
+
+    /// Save the machine state at the end of a headless run
+    #[arg(long, value_name = "FILE", requires = "headless")]
+    save_state: Option<String>,
+
+    /// Feed the switches and buttons from a script, e.g. `at 12000: press A; at 15000: release A`
+    #[arg(long, value_name = "FILE")]
+    input_script: Option<String>,
+
+    /// Record the switches and buttons used in the window to a script for --input-script
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["headless", "input_script"])]
+    record_input: Option<String>,
+
+    /// Record every executed instruction to this file
+    #[arg(long, value_name = "FILE")]
+    trace: Option<String>,
+
+    /// Format of the trace file
+    #[arg(long, value_enum, default_value_t = TraceFormat::Text, requires = "trace")]
+    trace_format: TraceFormat,
+
+    /// Only trace instructions in this range, e.g. 0x10:0x20, or .label for the code from the label up to the next call target
+    #[arg(long, value_name = "START:END", requires = "trace")]
+    trace_range: Vec<String>,
+
+    /// Frequency of the board clock in Hz
+    #[arg(long, value_name = "HZ", default_value_t = TimingModel::BASYS3.clock_hz)]
+    clock_hz: u64,
+
+    /// Board clock cycles per pipeline stage
+    #[arg(long, value_name = "N", default_value_t = TimingModel::BASYS3.divider)]
+    clock_divider: u64,
+
+    /// Pipeline stages per instruction
+    #[arg(long, value_name = "N", default_value_t = TimingModel::BASYS3.stages_per_instr)]
+    stages: u64,
+
+    /// Pace the window by elapsed wall-clock time, or by rendered frames
+    #[arg(long, value_enum, default_value_t = SyncMode::Wall)]
+    sync: SyncMode,
+
+    #[command(flatten)]
+    hardware: ProfileArgs,
+
+    /// What a data access past the memory of the profile does
+    #[arg(long, value_enum, default_value_t = OutOfRange::Fault)]
+    out_of_range: OutOfRange,
+
+    /// Bytes for the program to receive over the UART, from a file or `-` for stdin
+    #[arg(long, value_name = "FILE")]
+    uart_input: Option<String>,
+
+    /// Seed for the random number generator at RNG_ADDR. Without it, the window seeds from the
+    /// clock, and headless runs use a fixed seed so that they can be repeated
+    #[arg(long, value_name = "N", value_parser = parse_seed)]
+    seed: Option<i16>,
+
+    /// Initial speed of the window relative to the board, e.g. 0.5, 10x or unlimited
+    #[arg(long, value_name = "MULTIPLIER", value_parser = parse_speed, default_value = "1")]
+    speed: Speed,
+
+    /// Save the display as a PNG once a headless run has executed COUNT instructions
+    #[arg(long, value_name = "COUNT:FILE", value_parser = parse_screenshot, requires = "headless")]
+    screenshot: Vec<(u64, String)>,
+
+    /// Save the display as numbered PNGs in this directory, every drawn frame in the window
+    #[arg(long, value_name = "DIR")]
+    record_frames: Option<String>,
+
+    /// Instructions between the frames recorded by a headless run, by default a 60th of a second
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "record_frames",
+        requires = "headless"
+    )]
+    frame_interval: Option<u64>,
+
+    /// Scale up screenshots and recorded frames by this factor
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=16))]
+    capture_scale: u32,
+
+    /// Time N frames drawing the monitor with a rectangle per pixel and N with a texture, then exit
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "headless")]
+    bench_frames: Option<u32>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print a listing of an assembled code file
+    Disasm {
+        /// Name of the code file to disassemble
+        #[arg(default_value = CODE_FILE_NAME)]
+        filename: String,
+
+        /// Read the file as raw bytes (3 per instruction) instead of a hex file
+        #[arg(long)]
+        raw: bool,
+    },
+    /// Assemble a program and step through it in an interactive debugger
+    Debug {
+        /// Name of input file containing assembly
+        filename: String,
+
+        #[command(flatten)]
+        hardware: ProfileArgs,
+    },
+    /// Assemble a program and wait for gdb to attach over TCP
+    Gdb {
+        /// Name of input file containing assembly
+        filename: String,
+
+        /// Port to listen on (localhost only)
+        #[arg(long, default_value_t = 1234)]
+        port: u16,
+
+        #[command(flatten)]
+        hardware: ProfileArgs,
+    },
+    /// Serve the Debug Adapter Protocol on stdin/stdout, for debugging from an editor
+    Dap,
+    /// Run the assembly tests for a program, from NAME.tests and `;!` lines in the program
+    Test {
+        /// Name of input file containing assembly
+        filename: String,
+
+        /// Read the tests from this file instead of NAME.tests
+        #[arg(long, value_name = "FILE")]
+        tests: Option<String>,
+
+        /// Fail a test that runs more than this many instructions, unless it sets its own budget
+        #[arg(long, value_name = "N", default_value_t = DEFAULT_BUDGET)]
+        budget: u64,
+
+        /// Only run tests whose name contains this
+        filter: Option<String>,
+
+        #[command(flatten)]
+        hardware: ProfileArgs,
+    },
+}
+
+#[derive(Args)]
+struct ProfileArgs {
+    /// Memory sizes to enforce: the basys3's, or all 65536 data words and any number of instructions
+    #[arg(long, value_enum, default_value_t = HardwareProfile::Basys3)]
+    profile: HardwareProfile,
 }
 
-const CODE_FILE_NAME: &str = "seq.code";
+const STATE_FILE_NAME: &str = "machine.state";
 
-#[macroquad::main("Assembler Emulator")]
-async fn main() {
+fn main() {
     let cli = Cli::parse();
-    let input_filepath = cli.filename;
 
-    let mut contents = String::new();
-    File::open(&input_filepath)
-        .expect(&format!("could not open file: {}", &input_filepath))
-        .read_to_string(&mut contents)
-        .expect(&format!("error reading file: {}", &input_filepath));
+    match cli.command {
+        Some(Command::Disasm { filename, raw }) => {
+            run_disassembler(&filename, raw);
+            return;
+        }
+        Some(Command::Debug { filename, hardware }) => {
+            let program = load_program(&filename, hardware.profile);
+            let mut cpu_emulator = CpuEmu::new(program.code);
+            cpu_emulator.set_profile(hardware.profile, OutOfRange::Fault);
+            let mut debugger = Debugger::new(cpu_emulator, program.label_map, program.var_loc_map);
+            debugger.run_repl(std::io::stdin().lock(), &mut std::io::stdout());
+            return;
+        }
+        Some(Command::Gdb {
+            filename,
+            port,
+            hardware,
+        }) => {
+            let program = load_program(&filename, hardware.profile);
+            let mut cpu_emulator = CpuEmu::new(program.code);
+            cpu_emulator.set_profile(hardware.profile, OutOfRange::Fault);
+            let mut stub = GdbStub::new(cpu_emulator);
+            if let Err(e) = stub.listen(port) {
+                exit_with_message(&format!("gdb connection failed: {}", e));
+            }
+            return;
+        }
+        Some(Command::Dap) => {
+            if let Err(e) = DapServer::new(std::io::stdout()).serve(std::io::stdin()) {
+                exit_with_message(&format!("debug adapter failed: {}", e));
+            }
+            return;
+        }
+        Some(Command::Test {
+            filename,
+            tests,
+            budget,
+            filter,
+            hardware,
+        }) => {
+            run_test_command(
+                &filename,
+                tests.as_deref(),
+                budget,
+                filter.as_deref(),
+                hardware.profile,
+            );
+            return;
+        }
+        None => {}
+    }
+
+    let input_filepath = cli.filename.unwrap();
+
+    let program = load_program(&input_filepath, cli.hardware.profile);
+    let mut cpu_emulator = CpuEmu::new(program.code);
+    cpu_emulator.set_profile(cli.hardware.profile, cli.out_of_range);
+    let timing = TimingModel::new(cli.clock_hz, cli.clock_divider, cli.stages)
+        .unwrap_or_else(|e| exit_with_message(&e));
+    cpu_emulator.set_timing(timing);
+    let seed = match cli.seed {
+        Some(seed) => seed,
+        None if cli.headless => DEFAULT_SEED as i16,
+        None => seed_from_clock(),
+    };
+    cpu_emulator.set_mem(RNG_ADDR, seed);
+    if let Some(state_file) = &cli.load_state {
+        let state = MachineState::load(state_file).unwrap_or_else(|e| exit_with_message(&e));
+        if let Err(e) = cpu_emulator.set_state(&state) {
+            exit_with_message(&format!("{}: {}", state_file, e));
+        }
+    }
+
+    for location in &cli.breakpoints {
+        match resolve_address(location, &program.label_map, &program.var_loc_map) {
+            Some(addr) => cpu_emulator.add_breakpoint(addr),
+            None => exit_with_message(&format!("unknown breakpoint location `{}`", location)),
+        }
+    }
+    for watch in &cli.watchpoints {
+        match parse_watchpoint(watch, &program.label_map, &program.var_loc_map) {
+            Ok((addr, kind)) => cpu_emulator.add_watchpoint(addr, kind),
+            Err(e) => exit_with_message(&e),
+        }
+    }
 
-    let var_loc_map = create_location_map("vars.locations");
+    if let Some(script_file) = &cli.input_script {
+        let script = InputScript::load(script_file).unwrap_or_else(|e| exit_with_message(&e));
+        cpu_emulator.set_input_script(script);
+    }
 
-    let (mut verbs, map) = get_tokens(contents, &var_loc_map);
+    // the window reads stdin as it comes in, everything else waits for all of it
+    let mut uart_stdin = None;
+    match cli.uart_input.as_deref() {
+        Some("-") if !cli.headless => uart_stdin = Some(spawn_stdin_reader()),
+        Some(input_file) => {
+            let bytes = read_uart_input(input_file).unwrap_or_else(|e| exit_with_message(&e));
+            cpu_emulator.send_uart_input(&bytes);
+        }
+        None => {}
+    }
 
-    resolve_labels(&mut verbs, &map);
+    if let Some(trace_file) = &cli.trace {
+        let ranges = cli
+            .trace_range
+            .iter()
+            .map(|range| {
+                parse_trace_range(
+                    range,
+                    cpu_emulator.get_instrs(),
+                    &program.label_map,
+                    &program.var_loc_map,
+                )
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .unwrap_or_else(|e| exit_with_message(&e));
+        let tracer = Tracer::create(trace_file, cli.trace_format, ranges)
+            .unwrap_or_else(|e| exit_with_message(&e));
+        cpu_emulator.set_tracer(tracer);
+    }
 
-    let mut f = File::create(CODE_FILE_NAME).expect("error creating output file.");
+    let frames = cli.record_frames.as_deref().map(|dir| {
+        FrameRecorder::create(dir, cli.capture_scale).unwrap_or_else(|e| exit_with_message(&e))
+    });
 
-    for verb in &verbs {
-        f.write(verb.as_hex_file_line().as_bytes())
-            .expect("error writing to output file");
-        f.write("\n".as_bytes())
-            .expect("error writing to output file");
+    if cli.headless {
+        let mut capture = Capture::new(cli.screenshot, cli.capture_scale);
+        if let Some(frames) = frames {
+            let interval = cli.frame_interval.unwrap_or_else(|| {
+                timing.duration_to_cycles(FRAME_DURATION) / timing.cycles_per_instr()
+            });
+            capture.record_frames(frames, interval, cpu_emulator.get_instructions_executed());
+        }
+        let outcome = run_headless(&mut cpu_emulator, cli.max_instructions, &mut capture)
+            .unwrap_or_else(|e| exit_with_message(&e));
+        let trace_res = cpu_emulator.flush_trace();
+        cpu_emulator.end_uart_line();
+        print_outcome(&cpu_emulator, &outcome);
+        for (count, file) in capture.get_missed_screenshots() {
+            eprintln!(
+                "warning: no screenshot saved to {}, the run ended before {} instructions",
+                file, count
+            );
+        }
+        if cli.dump_regs {
+            dump_regs(&cpu_emulator);
+        }
+        if let Some((start, end)) = cli.dump_mem {
+            dump_mem(&cpu_emulator, start, end);
+        }
+        if let Some(state_file) = &cli.save_state {
+            if let Err(e) = cpu_emulator.get_state().save(state_file) {
+                exit_with_message(&e);
+            }
+        }
+        if let Err(e) = trace_res {
+            exit_with_message(&e);
+        }
+        std::process::exit(outcome.exit_code());
     }
-    println!(
-        "Wrote output to file {}. {} instruction words ({} bits)",
-        CODE_FILE_NAME,
-        verbs.len(),
-        verbs.len() * 24
+
+    let recorder = cli.record_input.map(|record_file| {
+        InputRecorder::create(
+            &record_file,
+            cpu_emulator.get_switch_states(),
+            cpu_emulator.get_button_states(),
+        )
+        .unwrap_or_else(|e| exit_with_message(&e))
+    });
+    let bench = cli.bench_frames.map(FrameBench::new);
+    let conf = Conf {
+        window_title: "Assembler Emulator".to_string(),
+        platform: Platform {
+            // so that the benchmark's frames are not held back to the display's refresh rate
+            swap_interval: bench.as_ref().map(|_| 0),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    macroquad::Window::from_config(
+        conf,
+        run_window(
+            cpu_emulator,
+            recorder,
+            uart_stdin,
+            cli.sync,
+            cli.speed,
+            bench,
+            WindowCapture::new(frames, cli.capture_scale),
+        ),
     );
+}
+
+fn load_program(input_filepath: &str, profile: HardwareProfile) -> Program {
+    program::load_program(input_filepath, profile).unwrap_or_else(|e| exit_with_text(&e))
+}
 
-    let mut cpu_emulator = CpuEmu::new(verbs);
-    let mut curr_switch_states = 0i16;
+fn exit_with_message(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(1);
+}
+
+fn exit_with_text(text: &str) -> ! {
+    // for errors that are already formatted, like rendered assembly errors
+    eprintln!("{}", text);
+    std::process::exit(1);
+}
+
+async fn run_window(
+    mut cpu_emulator: CpuEmu,
+    mut recorder: Option<InputRecorder>,
+    uart_stdin: Option<Receiver<Vec<u8>>>,
+    sync: SyncMode,
+    speed: Speed,
+    mut bench: Option<FrameBench>,
+    mut capture: WindowCapture,
+) {
+    let mut monitor = Monitor::new();
+    let mut curr_switch_states = cpu_emulator.get_switch_states();
+    let mut last_frame = Instant::now();
+    let mut speed_control = SpeedControl::new(speed, cpu_emulator.get_instructions_executed());
+    let mut fault = None;
 
     loop {
         clear_background(LIGHTGRAY);
 
+        handle_state_hotkeys(&mut cpu_emulator, &mut curr_switch_states);
+        capture.handle_keys(&cpu_emulator);
+
+        // the board's I/O goes below and to the right of the monitor
+        let (monitor_w, monitor_h) = monitor_size();
+        let (side_x, bottom_y) = (monitor_w + 20.0, monitor_h + 10.0);
+
         let gfx_buf = cpu_emulator.get_gfx_buffer();
+        let monitor_start = Instant::now();
+        let mut uploaded = false;
+        if bench.as_ref().is_some_and(|bench| bench.is_drawing_rects()) {
+            draw_monitor_rects(10.0, 10.0, monitor_w, monitor_h, gfx_buf);
+        } else {
+            uploaded = monitor.update(gfx_buf);
+            monitor.draw(10.0, 10.0, monitor_w, monitor_h);
+        }
+        let monitor_time = monitor_start.elapsed();
+
+        draw_leds(10.0, bottom_y + 10.0, cpu_emulator.get_led_output()).await;
+        draw_seven_segment(side_x, bottom_y + 16.0, &cpu_emulator.get_seven_segment()).await;
+        if cpu_emulator.has_input_script() {
+            // the script owns the inputs, the switches only show what it has set
+            let mut shown_switch_states = cpu_emulator.get_switch_states();
+            draw_switches(10.0, bottom_y + 30.0, &mut shown_switch_states).await;
+        } else {
+            draw_switches(10.0, bottom_y + 30.0, &mut curr_switch_states).await;
+            let button_states = get_curr_button_states().await;
+            cpu_emulator.set_switch_states(curr_switch_states);
+            cpu_emulator.set_button_states(button_states);
+            if let Some(recorder) = &mut recorder {
+                recorder.record(
+                    cpu_emulator.get_instructions_executed(),
+                    curr_switch_states,
+                    button_states,
+                );
+            }
+        }
 
-        draw_monitor(10.0, 10.0, 640.0, 480.0, gfx_buf).await;
+        if let Some(uart_stdin) = &uart_stdin {
+            while let Ok(bytes) = uart_stdin.try_recv() {
+                cpu_emulator.send_uart_input(&bytes);
+            }
+        }
 
-        draw_leds(10.0, 500.0, cpu_emulator.get_led_output()).await;
-        draw_switches(10.0, 520.0, &mut curr_switch_states).await;
-        cpu_emulator.set_switch_states(curr_switch_states);
-        cpu_emulator.set_button_states(get_curr_button_states().await);
+        speed_control.handle_keys();
+        let now = Instant::now();
+        if speed_control.take_frame() {
+            // a frame advanced while paused runs one frame's worth of time, however long it was paused
+            let elapsed = match sync {
+                SyncMode::Wall if !speed_control.paused => (now - last_frame).min(MAX_CATCH_UP),
+                _ => FRAME_DURATION,
+            };
+            // a fault leaves the machine as it was, so running again just gives the same fault
+            // until a state is loaded with F9
+            let res = match speed_control.speed {
+                Speed::Multiplier(speed) => cpu_emulator.run_for(elapsed.mul_f64(speed)),
+                Speed::Unlimited => cpu_emulator.run_flat_out(UNLIMITED_FRAME_BUDGET),
+            };
+            fault = res.err();
+        }
+        last_frame = now;
+        if let Err(e) = cpu_emulator.flush_trace() {
+            eprintln!("error: {}", e);
+        }
+        capture.capture_frame(&cpu_emulator);
 
-        cpu_emulator.run_some_instructions();
+        let rate = speed_control.update_rate(cpu_emulator.get_instructions_executed());
+        draw_speed_overlay(side_x, 30.0, &speed_control, rate, cpu_emulator.is_halted());
+        if let Some(fault) = &fault {
+            draw_fault(10.0, bottom_y + 82.0, fault);
+        }
 
         next_frame().await;
+
+        if let Some(bench) = &mut bench {
+            if bench.record(monitor_time, uploaded) {
+                cpu_emulator.end_uart_line();
+                bench.print_report();
+                std::process::exit(0);
+            }
+        }
+    }
+}
+
+fn monitor_size() -> (f32, f32) {
+    // the largest 4:3 size that leaves room for the I/O, 640x480 in the default 800x600 window
+    let scale = ((screen_width() - 160.0) / SCREEN_WIDTH as f32)
+        .min((screen_height() - 120.0) / SCREEN_HEIGHT as f32)
+        .max(1.0);
+    (SCREEN_WIDTH as f32 * scale, SCREEN_HEIGHT as f32 * scale)
+}
+
+fn handle_state_hotkeys(cpu_emulator: &mut CpuEmu, curr_switch_states: &mut i16) {
+    // F5 saves the whole machine to STATE_FILE_NAME, F9 loads it back
+    if is_key_pressed(KeyCode::F5) {
+        match cpu_emulator.get_state().save(STATE_FILE_NAME) {
+            Ok(()) => println!("saved machine state to {}", STATE_FILE_NAME),
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+    if is_key_pressed(KeyCode::F9) {
+        let res =
+            MachineState::load(STATE_FILE_NAME).and_then(|state| cpu_emulator.set_state(&state));
+        match res {
+            Ok(()) => {
+                *curr_switch_states = cpu_emulator.get_switch_states();
+                println!("loaded machine state from {}", STATE_FILE_NAME);
+            }
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+}
+
+fn run_test_command(
+    filepath: &str,
+    tests_path: Option<&str>,
+    budget: u64,
+    filter: Option<&str>,
+    profile: HardwareProfile,
+) {
+    let (_, program) = program::assemble(filepath, profile).unwrap_or_else(|e| exit_with_text(&e));
+    let mut tests =
+        load_tests(filepath, tests_path, &program).unwrap_or_else(|e| exit_with_message(&e));
+    if let Some(filter) = filter {
+        tests.retain(|test| test.name.contains(filter));
+    }
+    if tests.is_empty() {
+        exit_with_message(&format!("no tests found for {}", filepath));
+    }
+
+    if !run_tests(&tests, &program, budget, profile) {
+        std::process::exit(1);
+    }
+}
+
+fn run_disassembler(filepath: &str, raw: bool) {
+    let words = if raw {
+        let bytes = std::fs::read(filepath)
+            .unwrap_or_else(|_| exit_with_message(&format!("could not open file: {}", filepath)));
+        parse_raw_code(&bytes)
+    } else {
+        parse_code_file(&read_file(filepath).unwrap_or_else(|e| exit_with_text(&e)))
+            .map_err(|e| e.to_string())
+    };
+
+    match words {
+        Ok(words) => print!("{}", disassemble(&words)),
+        Err(e) => {
+            eprintln!("error: {}: {}", filepath, e);
+            std::process::exit(1);
+        }
     }
 }
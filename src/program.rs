@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use crate::assemble_error::{render_errors, AssembleError, Span};
+use crate::code_file::parse_code_file;
+use crate::hardware::HardwareProfile;
+use crate::instr_repr::Verb;
+use crate::label_resolver::resolve_labels;
+use crate::location_resolver::create_location_map;
+use crate::tokens::get_tokens;
+
+pub const CODE_FILE_NAME: &str = "seq.code";
+pub const LOCATIONS_FILE_NAME: &str = "vars.locations";
+
+// errors from this module are returned as text that is ready to be printed,
+// since assembly errors already render their own source snippets.
+
+pub struct Program {
+    pub code: Vec<[u8; 3]>,
+    pub label_map: HashMap<String, u16>,
+    pub var_loc_map: HashMap<String, u16>,
+    // source span of each instruction word. Empty when the program was loaded from a code file.
+    pub spans: Vec<Span>,
+}
+
+pub fn load_program(input_filepath: &str, profile: HardwareProfile) -> Result<Program, String> {
+    if input_filepath.ends_with(".code") {
+        // already assembled, e.g. by another tool. Run it as is.
+        let var_loc_map = if Path::new(LOCATIONS_FILE_NAME).exists() {
+            load_locations(Path::new(""))?
+        } else {
+            HashMap::new()
+        };
+        let code = load_code_file(input_filepath)?;
+        check_program_size(&code, input_filepath, profile)?;
+        return Ok(Program {
+            code,
+            label_map: HashMap::new(),
+            var_loc_map,
+            spans: Vec::new(),
+        });
+    }
+
+    let (verbs, program) = assemble(input_filepath, profile)?;
+    write_code_file(&verbs);
+    Ok(program)
+}
+
+pub fn assemble(
+    input_filepath: &str,
+    profile: HardwareProfile,
+) -> Result<(Vec<Verb>, Program), String> {
+    assemble_in(Path::new(""), input_filepath, profile)
+}
+
+pub fn assemble_in(
+    dir: &Path,
+    input_filepath: &str,
+    profile: HardwareProfile,
+) -> Result<(Vec<Verb>, Program), String> {
+    // relative paths, including the one to vars.locations, are taken from `dir` rather than the
+    // working directory
+    let input_filepath = &dir.join(input_filepath).to_string_lossy().into_owned();
+    let contents = read_file(input_filepath)?;
+    let var_loc_map = load_locations(dir)?;
+
+    let ((mut verbs, label_map, spans), mut errors) = get_tokens(contents.clone(), &var_loc_map);
+    // labels are resolved even when some lines had errors, so that all of them are reported
+    if let Err(label_errors) = resolve_labels(&mut verbs, &label_map, &spans) {
+        errors.extend(label_errors);
+    }
+    if !errors.is_empty() {
+        // render_errors puts them in source order
+        return Err(format_errors(&errors, input_filepath, &contents));
+    }
+
+    let code: Vec<[u8; 3]> = verbs.iter().map(Verb::to_bytes).collect();
+    check_program_size(&code, input_filepath, profile)?;
+
+    let program = Program {
+        code,
+        label_map,
+        var_loc_map,
+        spans,
+    };
+    Ok((verbs, program))
+}
+
+fn write_code_file(verbs: &[Verb]) {
+    let mut f = File::create(CODE_FILE_NAME).expect("error creating output file.");
+
+    for verb in verbs {
+        f.write_all(verb.as_hex_file_line().as_bytes())
+            .expect("error writing to output file");
+        f.write_all("\n".as_bytes())
+            .expect("error writing to output file");
+    }
+    println!(
+        "Wrote output to file {}. {} instruction words ({} bits)",
+        CODE_FILE_NAME,
+        verbs.len(),
+        verbs.len() * 24
+    );
+}
+
+pub fn load_code_file(filepath: &str) -> Result<Vec<[u8; 3]>, String> {
+    parse_code_file(&read_file(filepath)?).map_err(|e| format!("error: {}: {}", filepath, e))
+}
+
+pub fn load_locations(dir: &Path) -> Result<HashMap<String, u16>, String> {
+    let path = dir.join(LOCATIONS_FILE_NAME).to_string_lossy().into_owned();
+    let locations = read_file(&path)?;
+    create_location_map(locations.clone())
+        .map_err(|errors| format_errors(&errors, &path, &locations))
+}
+
+pub fn read_file(filepath: &str) -> Result<String, String> {
+    let mut contents = String::new();
+    File::open(filepath)
+        .map_err(|_| format!("error: could not open file: {}", filepath))?
+        .read_to_string(&mut contents)
+        .map_err(|_| format!("error: error reading file: {}", filepath))?;
+    Ok(contents)
+}
+
+fn check_program_size(
+    code: &[[u8; 3]],
+    input_filepath: &str,
+    profile: HardwareProfile,
+) -> Result<(), String> {
+    profile
+        .check_program_size(code)
+        .map_err(|e| format!("error: {}: {}", input_filepath, e))
+}
+
+fn format_errors(errors: &[AssembleError], file_name: &str, source: &str) -> String {
+    format!(
+        "{}error: could not assemble `{}` due to {} previous error{}",
+        render_errors(errors, file_name, source),
+        file_name,
+        errors.len(),
+        if errors.len() == 1 { "" } else { "s" }
+    )
+}
@@ -0,0 +1,155 @@
+// The basys3's 4 digit seven-segment display, driven by SEGMENT_DISP_LO_ADDR and SEGMENT_DISP_HI_ADDR.
+// Digit 0 is the rightmost one. Segment patterns use bit 0-6 for segments a-g
+// (clockwise from the top, g in the middle) and bit 7 for the decimal point.
+//
+// Hex mode (bit 15 of HI clear):
+//   LO        the 4 digits as hex, digit 3 in bits 12-15
+//   HI 0-3    bit n blanks digit n
+//   HI 4-7    bit 4+n lights the decimal point of digit n
+//
+// Raw mode (bit 15 of HI set):
+//   LO        segment pattern of digit 0 in bits 0-7, digit 1 in bits 8-15
+//   HI        digit 2 in bits 0-7, digit 3 in bits 8-14 (digit 3 has no decimal point)
+
+pub const RAW_MODE: i16 = 0x8000u16 as i16;
+pub const DECIMAL_POINT: u8 = 0x80;
+
+pub const HEX_GLYPHS: [u8; 16] = [
+    0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07, 0x7F, 0x6F, 0x77, 0x7C, 0x39, 0x5E, 0x79, 0x71,
+];
+// the middle segment alone, for showing a minus sign in raw mode
+const DASH: u8 = 0x40;
+
+pub fn segment_patterns(lo: i16, hi: i16) -> [u8; 4] {
+    // the lit segments of each digit, digit 0 first
+    let (lo, hi) = (lo as u16, hi as u16);
+    let mut digits = [0; 4];
+    for (n, digit) in digits.iter_mut().enumerate() {
+        *digit = if hi & RAW_MODE as u16 != 0 {
+            let word = if n < 2 { lo } else { hi & 0x7FFF };
+            (word >> (8 * (n % 2))) as u8
+        } else if hi & (1 << n) != 0 {
+            0
+        } else {
+            let dp = if hi & (1 << (4 + n)) != 0 {
+                DECIMAL_POINT
+            } else {
+                0
+            };
+            HEX_GLYPHS[((lo >> (4 * n)) & 0xF) as usize] | dp
+        };
+    }
+    digits
+}
+
+pub fn describe(patterns: &[u8; 4]) -> String {
+    // the digits as text, leftmost first: hex digits, `-`, `_` for a blank digit, `?` for any
+    // other pattern, and `.` after a digit with its decimal point lit
+    let mut res = String::new();
+    for pattern in patterns.iter().rev() {
+        let segments = pattern & !DECIMAL_POINT;
+        res.push(
+            match HEX_GLYPHS.iter().position(|glyph| *glyph == segments) {
+                Some(value) => char::from_digit(value as u32, 16)
+                    .unwrap()
+                    .to_ascii_uppercase(),
+                None if segments == DASH => '-',
+                None if segments == 0 => '_',
+                None => '?',
+            },
+        );
+        if pattern & DECIMAL_POINT != 0 {
+            res.push('.');
+        }
+    }
+    res
+}
+
+pub fn parse_display(s: &str) -> Result<[u8; 4], String> {
+    // the reverse of describe, e.g. `12.AB` or `__42`
+    let mut digits = Vec::new();
+    for c in s.chars() {
+        let pattern = match c {
+            '.' => {
+                let last = digits
+                    .last_mut()
+                    .ok_or(format!("`.` must follow a digit in `{}`", s))?;
+                *last |= DECIMAL_POINT;
+                continue;
+            }
+            '-' => DASH,
+            '_' => 0,
+            _ => match c.to_digit(16) {
+                Some(value) => HEX_GLYPHS[value as usize],
+                None => return Err(format!("invalid digit `{}` in `{}`", c, s)),
+            },
+        };
+        digits.push(pattern);
+    }
+    let mut res: [u8; 4] = digits
+        .try_into()
+        .map_err(|_| format!("expected 4 digits, found `{}`", s))?;
+    // digits are written leftmost first, but digit 0 is the rightmost one
+    res.reverse();
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_mode_shows_every_digit() {
+        for value in 0..16u16 {
+            let lo = (value << 12 | value << 8 | value << 4 | value) as i16;
+            let glyph = HEX_GLYPHS[value as usize];
+            assert_eq!(segment_patterns(lo, 0), [glyph; 4]);
+
+            let text = format!("{:X}", value).repeat(4);
+            assert_eq!(describe(&[glyph; 4]), text);
+            assert_eq!(parse_display(&text), Ok([glyph; 4]));
+            assert_eq!(parse_display(&text.to_lowercase()), Ok([glyph; 4]));
+        }
+        // digit 0 is the rightmost one
+        let patterns = segment_patterns(0x12AB, 0);
+        assert_eq!(patterns[0], HEX_GLYPHS[0xB]);
+        assert_eq!(patterns[3], HEX_GLYPHS[0x1]);
+        assert_eq!(describe(&patterns), "12AB");
+    }
+
+    #[test]
+    fn hex_mode_blanks_digits_and_lights_decimal_points() {
+        // digits 3 and 2 blank, the decimal point of digit 1 lit
+        let patterns = segment_patterns(0x0042, 0x0020 | 0x000C);
+        assert_eq!(
+            patterns,
+            [HEX_GLYPHS[2], HEX_GLYPHS[4] | DECIMAL_POINT, 0, 0]
+        );
+        assert_eq!(describe(&patterns), "__4.2");
+        assert_eq!(parse_display("__4.2"), Ok(patterns));
+
+        // a blanked digit stays dark even with its decimal point set
+        assert_eq!(segment_patterns(0x1234, 0x0011)[0], 0);
+    }
+
+    #[test]
+    fn raw_mode_passes_patterns_through() {
+        let patterns = segment_patterns(0x4086u16 as i16, RAW_MODE | 0x3F5B);
+        assert_eq!(patterns, [0x86, DASH, HEX_GLYPHS[2], HEX_GLYPHS[0]]);
+        assert_eq!(describe(&patterns), "02-1.");
+        assert_eq!(describe(&[0x01, 0, 0, 0]), "___?");
+    }
+
+    #[test]
+    fn bad_displays_are_rejected() {
+        assert!(parse_display("123").is_err());
+        assert!(parse_display("12345").is_err());
+        assert!(parse_display(".123").is_err());
+        assert!(parse_display("12G4").is_err());
+        assert_eq!(
+            parse_display("1.2.3.4."),
+            Ok([HEX_GLYPHS[4], HEX_GLYPHS[3], HEX_GLYPHS[2], HEX_GLYPHS[1]]
+                .map(|p| p | DECIMAL_POINT))
+        );
+    }
+}
@@ -16,6 +16,14 @@ impl SourceCodeCursor {
         }
     }
 
+    pub fn line(&self) -> usize {
+        self.curr_line
+    }
+
+    pub fn col(&self) -> usize {
+        self.curr_col
+    }
+
     pub fn peek(&self) -> Option<char> {
         self.contents.get(self.index).copied()
     }
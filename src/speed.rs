@@ -0,0 +1,145 @@
+use std::time::{Duration, Instant};
+
+use macroquad::prelude::*;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Speed {
+    // relative to the board's speed in the timing model
+    Multiplier(f64),
+    // as fast as the host can run, see UNLIMITED_FRAME_BUDGET
+    Unlimited,
+}
+
+impl Speed {
+    fn value(&self) -> f64 {
+        match self {
+            Speed::Multiplier(speed) => *speed,
+            Speed::Unlimited => f64::INFINITY,
+        }
+    }
+}
+
+// the speeds the window steps through with - and =
+pub const SPEEDS: [Speed; 10] = [
+    Speed::Multiplier(0.1),
+    Speed::Multiplier(0.25),
+    Speed::Multiplier(0.5),
+    Speed::Multiplier(1.0),
+    Speed::Multiplier(2.0),
+    Speed::Multiplier(5.0),
+    Speed::Multiplier(10.0),
+    Speed::Multiplier(100.0),
+    Speed::Multiplier(1000.0),
+    Speed::Unlimited,
+];
+
+// how much of each frame an unlimited speed spends running instructions, leaving time to render
+pub const UNLIMITED_FRAME_BUDGET: Duration = Duration::from_millis(12);
+
+// how often the displayed instruction rate is updated
+const RATE_INTERVAL: Duration = Duration::from_millis(500);
+
+pub fn parse_speed(s: &str) -> Result<Speed, String> {
+    // a multiplier like 0.5 or 10x, or `unlimited`
+    if s == "unlimited" || s == "max" {
+        return Ok(Speed::Unlimited);
+    }
+    match s.strip_suffix('x').unwrap_or(s).parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(Speed::Multiplier(speed)),
+        _ => Err(format!(
+            "invalid speed `{}`, expected a multiplier like 0.5 or 10x, or `unlimited`",
+            s
+        )),
+    }
+}
+
+pub struct SpeedControl {
+    pub speed: Speed,
+    pub paused: bool,
+    // set by the frame advance key while paused, cleared once the frame has run
+    advance: bool,
+    // for the displayed instruction rate
+    rate_start: Instant,
+    rate_start_count: u64,
+    rate: f64,
+}
+
+impl SpeedControl {
+    pub fn new(speed: Speed, instructions_executed: u64) -> Self {
+        SpeedControl {
+            speed,
+            paused: false,
+            advance: false,
+            rate_start: Instant::now(),
+            rate_start_count: instructions_executed,
+            rate: 0.0,
+        }
+    }
+
+    pub fn handle_keys(&mut self) {
+        // P pauses and resumes, . runs a single frame while paused, - and = change the speed
+        if is_key_pressed(KeyCode::P) {
+            self.paused = !self.paused;
+        }
+        if is_key_pressed(KeyCode::Period) && self.paused {
+            self.advance = true;
+        }
+        // a speed given on the command line may fall between steps, so step by value
+        let curr = self.speed.value();
+        if is_key_pressed(KeyCode::Minus) {
+            self.speed = SPEEDS
+                .iter()
+                .rev()
+                .find(|s| s.value() < curr)
+                .copied()
+                .unwrap_or(SPEEDS[0]);
+        }
+        if is_key_pressed(KeyCode::Equal) {
+            self.speed = SPEEDS
+                .iter()
+                .find(|s| s.value() > curr)
+                .copied()
+                .unwrap_or(Speed::Unlimited);
+        }
+    }
+
+    pub fn take_frame(&mut self) -> bool {
+        // whether instructions should run this frame
+        if !self.paused {
+            return true;
+        }
+        std::mem::take(&mut self.advance)
+    }
+
+    pub fn update_rate(&mut self, instructions_executed: u64) -> f64 {
+        let elapsed = self.rate_start.elapsed();
+        if elapsed >= RATE_INTERVAL {
+            let executed = instructions_executed.saturating_sub(self.rate_start_count);
+            self.rate = executed as f64 / elapsed.as_secs_f64();
+            self.rate_start = Instant::now();
+            self.rate_start_count = instructions_executed;
+        }
+        self.rate
+    }
+}
+
+pub fn draw_speed_overlay(x: f32, y: f32, control: &SpeedControl, rate: f64, halted: bool) {
+    let speed = match control.speed {
+        Speed::Multiplier(speed) => format!("speed {}x", speed),
+        Speed::Unlimited => "speed unlimited".to_string(),
+    };
+    let rate = if rate >= 1_000_000.0 {
+        format!("{:.2}M instr/s", rate / 1_000_000.0)
+    } else if rate >= 1000.0 {
+        format!("{:.1}K instr/s", rate / 1000.0)
+    } else {
+        format!("{:.0} instr/s", rate)
+    };
+    draw_text(&speed, x, y, 20.0, BLACK);
+    draw_text(&rate, x, y + 20.0, 20.0, BLACK);
+    if halted {
+        draw_text("HALTED", x, y + 45.0, 24.0, RED);
+    } else if control.paused {
+        draw_text("PAUSED", x, y + 45.0, 24.0, DARKBLUE);
+    }
+}
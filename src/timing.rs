@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+
+// the rate macroquad renders at with vsync on a 60 Hz monitor
+pub const FRAME_RATE: u64 = 60;
+pub const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / FRAME_RATE);
+
+// the most wall-clock time a single frame can catch up on, so that a stalled window
+// (e.g. while it is being dragged) does not run seconds of instructions at once
+pub const MAX_CATCH_UP: Duration = Duration::from_millis(250);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, ValueEnum)]
+pub enum SyncMode {
+    // run as many instructions as the hardware would in the time that actually passed
+    Wall,
+    // run a fixed share of a second per rendered frame, assuming FRAME_RATE
+    Frame,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TimingModel {
+    pub clock_hz: u64,
+    // cpu_unit.v advances one pipeline stage on every rising edge of clk_dv[9],
+    // so the stage clock is the board clock divided by 2^10
+    pub divider: u64,
+    // every instruction goes through all 8 stages, whatever it does
+    pub stages_per_instr: u64,
+}
+
+impl TimingModel {
+    // 100 MHz board clock, stepped down to ~97.7 KHz, 8 stages: ~12.2K instructions per second
+    pub const BASYS3: TimingModel = TimingModel {
+        clock_hz: 100_000_000,
+        divider: 1024,
+        stages_per_instr: 8,
+    };
+
+    pub fn new(clock_hz: u64, divider: u64, stages_per_instr: u64) -> Result<Self, String> {
+        if clock_hz == 0 || divider == 0 || stages_per_instr == 0 {
+            return Err("the clock, divider and stages per instruction must not be 0".to_string());
+        }
+        Ok(TimingModel {
+            clock_hz,
+            divider,
+            stages_per_instr,
+        })
+    }
+
+    pub fn cycles_per_instr(&self) -> u64 {
+        self.divider * self.stages_per_instr
+    }
+
+    pub fn instrs_per_second(&self) -> f64 {
+        self.clock_hz as f64 / self.cycles_per_instr() as f64
+    }
+
+    pub fn cycles_to_duration(&self, cycles: u64) -> Duration {
+        let secs = cycles / self.clock_hz;
+        let nanos = (cycles % self.clock_hz) as u128 * 1_000_000_000 / self.clock_hz as u128;
+        Duration::new(secs, nanos as u32)
+    }
+
+    pub fn duration_to_cycles(&self, duration: Duration) -> u64 {
+        (duration.as_nanos() * self.clock_hz as u128 / 1_000_000_000) as u64
+    }
+}
+
+impl Default for TimingModel {
+    fn default() -> Self {
+        TimingModel::BASYS3
+    }
+}
@@ -1,19 +1,28 @@
 use std::collections::HashMap;
 
 use crate::{
+    assemble_error::{AssembleError, Span},
     instr_repr::{Operand, Reg, Verb},
     source_cursor::SourceCodeCursor,
 };
 
+// the parsed instructions, the label map, and the source location of each instruction
+pub type Tokens = (Vec<Verb>, HashMap<String, u16>, Vec<Span>);
+
 pub fn get_tokens(
     source_code_contents: String,
     var_loc_map: &HashMap<String, u16>,
-) -> (Vec<Verb>, HashMap<String, u16>) {
+) -> (Tokens, Vec<AssembleError>) {
+    // also returns the errors. A line that could not be parsed still takes up an instruction
+    // word, so that the labels after it keep their addresses and can be resolved.
     let mut cursor = SourceCodeCursor::new(source_code_contents);
 
     let mut label_map = HashMap::new();
 
     let mut verbs = Vec::new();
+    // spans[i] is the location of verbs[i] in the source code
+    let mut spans = Vec::new();
+    let mut errors = Vec::new();
 
     while cursor.peek().is_some() {
         // this loop will consume one line per iteration:
@@ -29,6 +38,7 @@ pub fn get_tokens(
             Some('.') => {
                 // parse label
                 let mut label_name: String = String::new();
+                let span_start = (cursor.line(), cursor.col());
 
                 while cursor.peek().is_some() && !cursor.peek().unwrap().is_ascii_whitespace() {
                     label_name.push(cursor.next().unwrap());
@@ -36,23 +46,37 @@ pub fn get_tokens(
 
                 consume_rest_of_line(&mut cursor);
 
+                if label_map.contains_key(&label_name) {
+                    errors.push(AssembleError::new(
+                        format!("label `{}` is defined more than once", label_name),
+                        Span::new(span_start.0, span_start.1, label_name.chars().count()),
+                    ));
+                    continue;
+                }
                 label_map.insert(label_name, verbs.len() as u16);
                 continue;
             }
 
-            _ => {
-                let verb = parse_verb(&mut cursor, var_loc_map);
-
-                verbs.push(verb);
-            }
+            _ => match parse_verb(&mut cursor, var_loc_map) {
+                Ok((verb, span)) => {
+                    verbs.push(verb);
+                    spans.push(span);
+                }
+                // keep going, so that every error in the file gets reported at once
+                Err(e) => {
+                    verbs.push(Verb::Nop);
+                    spans.push(e.span);
+                    errors.push(e);
+                }
+            },
         }
     }
 
-    return (verbs, label_map);
+    ((verbs, label_map, spans), errors)
 }
 
 pub fn consume_rest_of_line(cursor: &mut SourceCodeCursor) {
-    while cursor.peek() != Some('\n') && cursor.peek() != None {
+    while cursor.peek() != Some('\n') && cursor.peek().is_some() {
         cursor.next();
     }
     // consume newline if there is one
@@ -65,163 +89,213 @@ pub fn consume_whitespace(cursor: &mut SourceCodeCursor) {
     }
 }
 
-fn parse_verb(cursor: &mut SourceCodeCursor, var_loc_map: &HashMap<String, u16>) -> Verb {
+fn parse_verb(
+    cursor: &mut SourceCodeCursor,
+    var_loc_map: &HashMap<String, u16>,
+) -> Result<(Verb, Span), AssembleError> {
     let mut verb_name: String = String::new();
 
     consume_whitespace(cursor);
+    let (line, col) = (cursor.line(), cursor.col());
     while cursor.peek().is_some() && cursor.peek().unwrap().is_alphabetic() {
         verb_name.push(cursor.next().unwrap());
     }
+    if verb_name.is_empty() {
+        // take the whole token so that the error message shows what was found
+        while cursor.peek().is_some() && !cursor.peek().unwrap().is_ascii_whitespace() {
+            verb_name.push(cursor.next().unwrap());
+        }
+    }
+    let verb_span = Span::new(line, col, verb_name.chars().count());
 
-    match verb_name.as_str() {
+    let result = parse_verb_operands(cursor, var_loc_map, &verb_name, verb_span);
+    let span = Span::new(line, col, cursor.col().saturating_sub(col));
+    // skip past the rest of the line even if there was an error, so that parsing can resume
+    consume_rest_of_line(cursor);
+
+    result.map(|verb| (verb, span))
+}
+
+fn parse_verb_operands(
+    cursor: &mut SourceCodeCursor,
+    var_loc_map: &HashMap<String, u16>,
+    verb_name: &str,
+    verb_span: Span,
+) -> Result<Verb, AssembleError> {
+    match verb_name {
         "mov" => {
-            let operand_1 = parse_operand(cursor, var_loc_map);
-            let operand_2 = parse_operand(cursor, var_loc_map);
-            consume_rest_of_line(cursor);
+            let operand_1 = parse_operand(cursor, var_loc_map)?;
+            let operand_2 = parse_operand(cursor, var_loc_map)?;
 
             match (operand_1, operand_2) {
-                (Some(o1), Some(o2)) => match (&o1, &o2) {
+                (Some((o1, s1)), Some((o2, s2))) => match (&o1, &o2) {
                     (Operand::Reg(_), Operand::Imm(_))
+                    | (Operand::Reg(_), Operand::Label(_))
                     | (Operand::Reg(_), Operand::MemAtImm(_))
                     | (Operand::MemAtImm(_), Operand::Reg(_))
                     | (Operand::Reg(_), Operand::Reg(_))
                     | (Operand::Reg(_), Operand::MemAtReg(_))
-                    | (Operand::MemAtReg(_), Operand::Reg(_)) => return Verb::Mov(o1, o2),
-                    _ => panic!("invalid operands for mov"),
+                    | (Operand::MemAtReg(_), Operand::Reg(_)) => Ok(Verb::Mov(o1, o2)),
+                    _ => Err(AssembleError::new("invalid operands for mov", s1.to(s2))),
                 },
-                _ => panic!("not enough operands for mov"),
+                _ => Err(AssembleError::new(
+                    "not enough operands for mov",
+                    missing_operand_span(cursor),
+                )),
             }
         }
 
         "jmp" => {
-            let operand = parse_operand(cursor, var_loc_map);
-            consume_rest_of_line(cursor);
+            let operand = parse_operand(cursor, var_loc_map)?;
             match operand {
-                Some(o1) => match &o1 {
-                    Operand::Imm(_) | Operand::Label(_) => return Verb::Jmp(o1),
-                    _ => panic!("invalid operands for jmp"),
+                Some((o1, s1)) => match &o1 {
+                    Operand::Imm(_) | Operand::Label(_) => Ok(Verb::Jmp(o1)),
+                    _ => Err(AssembleError::new("invalid operands for jmp", s1)),
                 },
-                _ => panic!("not enough operands for jmp"),
+                _ => Err(AssembleError::new(
+                    "not enough operands for jmp",
+                    missing_operand_span(cursor),
+                )),
             }
         }
         "jz" | "jnz" => {
-            let operand_1 = parse_operand(cursor, var_loc_map);
-            let operand_2 = parse_operand(cursor, var_loc_map);
-            consume_rest_of_line(cursor);
+            let operand_1 = parse_operand(cursor, var_loc_map)?;
+            let operand_2 = parse_operand(cursor, var_loc_map)?;
             match (operand_1, operand_2) {
-                (Some(o1), Some(o2)) => match (&o1, &o2) {
-                    (Operand::Imm(_) | Operand::Label(_), Operand::Reg(_)) => {
-                        match verb_name.as_str() {
-                            "jz" => return Verb::Jz(o1, o2),
-                            "jnz" => return Verb::Jnz(o1, o2),
-                            _ => unreachable!(),
-                        }
-                    }
-                    _ => panic!("invalid operands for conditional jump"),
+                (Some((o1, s1)), Some((o2, s2))) => match (&o1, &o2) {
+                    (Operand::Imm(_) | Operand::Label(_), Operand::Reg(_)) => match verb_name {
+                        "jz" => Ok(Verb::Jz(o1, o2)),
+                        "jnz" => Ok(Verb::Jnz(o1, o2)),
+                        _ => unreachable!(),
+                    },
+                    _ => Err(AssembleError::new(
+                        "invalid operands for conditional jump",
+                        s1.to(s2),
+                    )),
                 },
-                _ => panic!("not enough operands for conditional jump"),
+                _ => Err(AssembleError::new(
+                    "not enough operands for conditional jump",
+                    missing_operand_span(cursor),
+                )),
             }
         }
 
         "add" | "sub" | "and" | "or" | "shl" | "shr" => {
-            let operand_1 = parse_operand(cursor, var_loc_map);
-            let operand_2 = parse_operand(cursor, var_loc_map);
-            consume_rest_of_line(cursor);
+            let operand_1 = parse_operand(cursor, var_loc_map)?;
+            let operand_2 = parse_operand(cursor, var_loc_map)?;
             match (operand_1, operand_2) {
-                (Some(o1), Some(o2)) => match (&o1, &o2) {
+                (Some((o1, s1)), Some((o2, s2))) => match (&o1, &o2) {
                     (Operand::Reg(_), Operand::Reg(_)) | (Operand::Reg(_), Operand::Imm(_)) => {
-                        match verb_name.as_str() {
-                            "add" => return Verb::Add(o1, o2),
-                            "sub" => return Verb::Sub(o1, o2),
-                            "and" => return Verb::And(o1, o2),
-                            "or" => return Verb::Or(o1, o2),
-                            "shl" => return Verb::Shl(o1, o2),
-                            "shr" => return Verb::Shr(o1, o2),
+                        match verb_name {
+                            "add" => Ok(Verb::Add(o1, o2)),
+                            "sub" => Ok(Verb::Sub(o1, o2)),
+                            "and" => Ok(Verb::And(o1, o2)),
+                            "or" => Ok(Verb::Or(o1, o2)),
+                            "shl" => Ok(Verb::Shl(o1, o2)),
+                            "shr" => Ok(Verb::Shr(o1, o2)),
                             _ => unreachable!(),
                         }
                     }
-                    _ => panic!("invalid operands for arithmetic operator"),
+                    _ => Err(AssembleError::new(
+                        "invalid operands for arithmetic operator",
+                        s1.to(s2),
+                    )),
                 },
-                _ => panic!("not enough operands for arithmetic operator"),
+                _ => Err(AssembleError::new(
+                    "not enough operands for arithmetic operator",
+                    missing_operand_span(cursor),
+                )),
             }
         }
 
         "not" => {
-            let operand = parse_operand(cursor, var_loc_map);
-            consume_rest_of_line(cursor);
+            let operand = parse_operand(cursor, var_loc_map)?;
             match operand {
-                Some(Operand::Reg(_)) => return Verb::Not(operand.unwrap()),
-                _ => panic!("invalid operand for not"),
+                Some((o1 @ Operand::Reg(_), _)) => Ok(Verb::Not(o1)),
+                Some((_, s1)) => Err(AssembleError::new("invalid operand for not", s1)),
+                None => Err(AssembleError::new(
+                    "invalid operand for not",
+                    missing_operand_span(cursor),
+                )),
             }
         }
 
         "dbg" => {
-            let optional_operand = parse_operand(cursor, var_loc_map);
-            consume_rest_of_line(cursor);
+            let optional_operand = parse_operand(cursor, var_loc_map)?;
             match optional_operand {
-                None => return Verb::DbgRegs,
-                Some(operand) => match operand {
-                    Operand::Imm(_) => return Verb::Dbg(operand),
-                    _ => panic!("invalid operand for debug"),
+                None => Ok(Verb::DbgRegs),
+                Some((operand, s1)) => match operand {
+                    Operand::Imm(_) => Ok(Verb::Dbg(operand)),
+                    _ => Err(AssembleError::new("invalid operand for debug", s1)),
                 },
             }
         }
 
-        "nop" => {
-            consume_rest_of_line(cursor);
-            return Verb::Nop;
-        }
-        "halt" => {
-            consume_rest_of_line(cursor);
-            return Verb::Halt;
-        }
+        "nop" => Ok(Verb::Nop),
+        "halt" => Ok(Verb::Halt),
 
         "call" => {
-            let operand = parse_operand(cursor, var_loc_map);
-            consume_rest_of_line(cursor);
+            let operand = parse_operand(cursor, var_loc_map)?;
             match operand {
-                Some(o1) => match &o1 {
-                    Operand::Imm(_) | Operand::Label(_) => return Verb::Call(o1),
-                    _ => panic!("invalid operands for call"),
+                Some((o1, s1)) => match &o1 {
+                    Operand::Imm(_) | Operand::Label(_) => Ok(Verb::Call(o1)),
+                    _ => Err(AssembleError::new("invalid operands for call", s1)),
                 },
-                _ => panic!("not enough operands for call"),
+                _ => Err(AssembleError::new(
+                    "not enough operands for call",
+                    missing_operand_span(cursor),
+                )),
             }
         }
-        "ret" => {
-            consume_rest_of_line(cursor);
-            return Verb::Ret;
-        }
+        "ret" => Ok(Verb::Ret),
 
-        _ => panic!("unrecognized verb: {}", verb_name),
+        "ei" => Ok(Verb::Ei),
+        "di" => Ok(Verb::Di),
+        "iret" => Ok(Verb::Iret),
+
+        _ => Err(AssembleError::new(
+            format!("unrecognized verb: `{}`", verb_name),
+            verb_span,
+        )),
     }
 }
 
+fn missing_operand_span(cursor: &SourceCodeCursor) -> Span {
+    // points just past the last thing on the line, where the operand should have been
+    Span::new(cursor.line(), cursor.col(), 1)
+}
+
 fn parse_operand(
     cursor: &mut SourceCodeCursor,
     var_loc_map: &HashMap<String, u16>,
-) -> Option<Operand> {
+) -> Result<Option<(Operand, Span)>, AssembleError> {
     consume_whitespace(cursor);
 
-    if cursor.peek() == None || cursor.peek() == Some('\n') {
-        return None;
+    if cursor.peek().is_none() || cursor.peek() == Some('\n') || cursor.peek() == Some(';') {
+        return Ok(None);
     }
 
+    let (line, col) = (cursor.line(), cursor.col());
     let mut operand_str: String = String::new();
     while cursor.peek().is_some() && (!cursor.peek().unwrap().is_ascii_whitespace()) {
         operand_str.push(cursor.next().unwrap());
     }
+    let span = Span::new(line, col, operand_str.chars().count());
 
     if let Some(val) = convert_str_to_imm(&operand_str, var_loc_map) {
-        return Some(Operand::Imm(val));
+        return Ok(Some((Operand::Imm(val), span)));
     }
 
     if operand_str.starts_with('.') {
-        return Some(Operand::Label(operand_str));
+        return Ok(Some((Operand::Label(operand_str), span)));
     }
 
     if operand_str.starts_with('[') {
-        if !operand_str.ends_with(']') {
-            panic!("expected operand `{}` to end with `]`", operand_str);
+        if !operand_str.ends_with(']') || operand_str.len() < 2 {
+            return Err(AssembleError::new(
+                format!("expected operand `{}` to end with `]`", operand_str),
+                span,
+            ));
         }
         let inner_string: String = operand_str
             .chars()
@@ -229,25 +303,31 @@ fn parse_operand(
             .take(operand_str.len() - 2)
             .collect();
         if let Some(val) = convert_str_to_imm(&inner_string, var_loc_map) {
-            return Some(Operand::MemAtImm(val));
-        }
-        match convert_str_to_reg(&inner_string) {
-            Some(reg) => return Some(Operand::MemAtReg(reg)),
-            None => panic!(
-                "expected either immediate address or register, found {}",
-                inner_string
-            ),
+            return Ok(Some((Operand::MemAtImm(val), span)));
         }
+        return match convert_str_to_reg(&inner_string) {
+            Some(reg) => Ok(Some((Operand::MemAtReg(reg), span))),
+            None => Err(AssembleError::new(
+                format!(
+                    "expected either immediate address or register, found `{}`",
+                    inner_string
+                ),
+                span,
+            )),
+        };
     }
 
     if let Some(reg) = convert_str_to_reg(&operand_str) {
-        return Some(Operand::Reg(reg));
+        return Ok(Some((Operand::Reg(reg), span)));
     }
 
-    panic!("invalid operand: `{}`", operand_str);
+    Err(AssembleError::new(
+        format!("invalid operand: `{}`", operand_str),
+        span,
+    ))
 }
 
-fn convert_str_to_reg(s: &str) -> Option<Reg> {
+pub fn convert_str_to_reg(s: &str) -> Option<Reg> {
     match s {
         "R0" | "r0" => Some(Reg::R0),
         "R1" | "r1" => Some(Reg::R1),
@@ -275,16 +355,51 @@ pub fn convert_str_to_imm(s: &str, var_loc_map: &HashMap<String, u16>) -> Option
         return Some(*val);
     }
 
-    let parse_res = if s.starts_with("0x") {
-        u64::from_str_radix(&s[2..], 16)
+    let parse_res = if let Some(hex_digits) = s.strip_prefix("0x") {
+        u64::from_str_radix(hex_digits, 16)
     } else {
         // dec
-        u64::from_str_radix(s, 10)
+        s.parse::<u64>()
     };
     match parse_res {
-        Ok(v) => {
-            return Some(v as u16);
-        }
+        Ok(v) => Some(v as u16),
         Err(_) => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_goes_on_after_a_bad_line() {
+        let source = "mov r1 5\nfoo r1 2\n.a\nmov 5 r1\n.a\n\tadd r1 [r99]\njmp\n.b\nhalt\n";
+        let ((verbs, label_map, spans), errors) = get_tokens(source.to_string(), &HashMap::new());
+        let errors: Vec<(&str, Span)> = errors
+            .iter()
+            .map(|e| (e.message.as_str(), e.span))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                ("unrecognized verb: `foo`", Span::new(2, 0, 3)),
+                ("invalid operands for mov", Span::new(4, 4, 4)),
+                ("label `.a` is defined more than once", Span::new(5, 0, 2)),
+                (
+                    "expected either immediate address or register, found `r99`",
+                    Span::new(6, 8, 5)
+                ),
+                ("not enough operands for jmp", Span::new(7, 3, 1)),
+            ]
+        );
+
+        // every line with an instruction still takes a word, so the labels keep their addresses
+        assert_eq!(verbs.len(), 6);
+        assert_eq!(verbs[0], Verb::Mov(Operand::Reg(Reg::R1), Operand::Imm(5)));
+        assert_eq!(verbs[1], Verb::Nop);
+        assert_eq!(verbs[5], Verb::Halt);
+        assert_eq!(spans[1], Span::new(2, 0, 3));
+        assert_eq!(label_map[".a"], 2);
+        assert_eq!(label_map[".b"], 5);
+    }
+}
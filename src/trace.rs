@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use clap::ValueEnum;
+use serde_json::json;
+
+use crate::emu::StepEvent;
+use crate::instr_repr::Verb;
+use crate::label_resolver::resolve_address;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, ValueEnum)]
+pub enum TraceFormat {
+    // one line per instruction, e.g.
+    //       12  0006:  mov R1 0x5            R1=0x0005
+    Text,
+    // one JSON object per instruction, e.g.
+    // {"instr":"mov R1 0x5","ip":6,"n":12,"reg":"R1","value":5,"word":"110005"}
+    Json,
+}
+
+pub struct Tracer {
+    out: BufWriter<File>,
+    filepath: String,
+    format: TraceFormat,
+    // inclusive instruction address ranges to record. Everything is recorded when empty.
+    ranges: Vec<(u16, u16)>,
+    // the first write that failed, e.g. with the disk full. Nothing is written after it.
+    error: Option<String>,
+}
+
+impl Tracer {
+    pub fn create(
+        filepath: &str,
+        format: TraceFormat,
+        ranges: Vec<(u16, u16)>,
+    ) -> Result<Self, String> {
+        let file = File::create(filepath)
+            .map_err(|e| format!("could not create trace file {}: {}", filepath, e))?;
+        Ok(Tracer {
+            out: BufWriter::new(file),
+            filepath: filepath.to_string(),
+            format,
+            ranges,
+            error: None,
+        })
+    }
+
+    pub fn record(
+        &mut self,
+        count: u64,
+        word: Option<[u8; 3]>,
+        event: &StepEvent,
+        regs: &[i16],
+        values: (Option<i16>, Option<i16>),
+    ) {
+        // `values` are the ones the bus gave the instruction and that it stored
+        if self.error.is_some() {
+            return;
+        }
+        if !self.ranges.is_empty()
+            && !self
+                .ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&event.ip))
+        {
+            return;
+        }
+
+        let instr = if event.interrupt {
+            format!("interrupt: {}", event.verb)
+        } else {
+            event.verb.to_string()
+        };
+        let instr = instr.trim_end();
+        let (read_value, stored_value) = values;
+        let line = match self.format {
+            TraceFormat::Text => {
+                let mut line = format!("{:>8}  {:0>4X}:  {:<22}", count, event.ip, instr);
+                if let Some(reg) = event.reg_written {
+                    let value = regs[reg.to_id() as usize];
+                    line.push_str(&format!(" {}=0x{:0>4X}", reg, value as u16));
+                }
+                if let (Some(addr), Some(value)) = (event.mem_read, read_value) {
+                    line.push_str(&format!(" [0x{:0>4X}]->0x{:0>4X}", addr, value as u16));
+                }
+                if let (Some(addr), Some(value)) = (event.mem_written, stored_value) {
+                    line.push_str(&format!(" [0x{:0>4X}]<-0x{:0>4X}", addr, value as u16));
+                }
+                if event.jump_taken {
+                    line.push_str(" (jump)");
+                }
+                line.trim_end().to_string()
+            }
+            TraceFormat::Json => {
+                let mut record = json!({ "n": count, "ip": event.ip, "instr": instr });
+                if let Some(word) = word {
+                    record["word"] =
+                        json!(format!("{:0>2x}{:0>2x}{:0>2x}", word[0], word[1], word[2]));
+                }
+                if let Some(reg) = event.reg_written {
+                    record["reg"] = json!(reg.to_string());
+                    record["value"] = json!(regs[reg.to_id() as usize]);
+                }
+                if let (Some(addr), Some(value)) = (event.mem_read, read_value) {
+                    record["mem_read"] = json!(addr);
+                    record["read_value"] = json!(value);
+                }
+                if let (Some(addr), Some(value)) = (event.mem_written, stored_value) {
+                    record["mem_write"] = json!(addr);
+                    record["write_value"] = json!(value);
+                }
+                for (key, set) in [
+                    ("jump", event.jump_taken),
+                    ("halted", event.halted),
+                    ("interrupt", event.interrupt),
+                ] {
+                    if set {
+                        record[key] = json!(true);
+                    }
+                }
+                record.to_string()
+            }
+        };
+        let res = writeln!(self.out, "{}", line);
+        self.check(res);
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        // returns the first error from writing the trace, which ends it
+        if self.error.is_none() {
+            let res = self.out.flush();
+            self.check(res);
+        }
+        match &self.error {
+            Some(e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    fn check(&mut self, res: io::Result<()>) {
+        if let Err(e) = res {
+            self.error = Some(format!(
+                "could not write to trace file {}: {}",
+                self.filepath, e
+            ));
+        }
+    }
+}
+
+pub fn parse_trace_range(
+    s: &str,
+    code: &[[u8; 3]],
+    label_map: &HashMap<String, u16>,
+    var_loc_map: &HashMap<String, u16>,
+) -> Result<(u16, u16), String> {
+    // START:END, a single address, or a label, which covers the code up to the next call target
+    let resolve = |v: &str| {
+        resolve_address(v, label_map, var_loc_map).ok_or(format!("unknown location `{}`", v))
+    };
+
+    if let Some((start, end)) = s.split_once(':') {
+        let (start, end) = (resolve(start)?, resolve(end)?);
+        if start > end {
+            return Err(format!(
+                "range start 0x{:X} is after its end 0x{:X}",
+                start, end
+            ));
+        }
+        return Ok((start, end));
+    }
+
+    let start = resolve(s)?;
+    if !s.starts_with('.') {
+        return Ok((start, start));
+    }
+    // loop labels inside a subroutine are not called, so only call targets end the range
+    let end = code
+        .iter()
+        .filter_map(|word| match Verb::from_bytes(*word) {
+            Ok(Verb::Call(target)) => Some(target.to_imm()),
+            _ => None,
+        })
+        .filter(|addr| *addr > start)
+        .min()
+        .map(|next| next - 1)
+        .unwrap_or(u16::MAX);
+    Ok((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instr_repr::{Operand, Reg};
+
+    fn event(ip: u16, verb: Verb) -> StepEvent {
+        StepEvent {
+            ip,
+            verb,
+            reg_written: None,
+            mem_read: None,
+            mem_written: None,
+            jump_taken: false,
+            halted: false,
+            interrupt: false,
+        }
+    }
+
+    fn trace(format: TraceFormat, ranges: Vec<(u16, u16)>) -> Vec<String> {
+        // records a load, a store and a call, and returns the lines written
+        let path = std::env::temp_dir().join(format!(
+            "asm_emu_trace_{:?}_{}_{}",
+            format,
+            ranges.len(),
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let mut tracer = Tracer::create(path, format, ranges).unwrap();
+        let mut regs = [0; 16];
+
+        let load = Verb::Mov(Operand::Reg(Reg::R2), Operand::MemAtImm(0x4C0));
+        let mut load_event = event(6, load.clone());
+        load_event.reg_written = Some(Reg::R2);
+        load_event.mem_read = Some(0x4C0);
+        regs[2] = -2;
+        tracer.record(
+            12,
+            Some(load.to_bytes()),
+            &load_event,
+            &regs,
+            (Some(-2), None),
+        );
+
+        let store = Verb::Mov(Operand::MemAtImm(0x4C1), Operand::Reg(Reg::R2));
+        let mut store_event = event(7, store.clone());
+        store_event.mem_written = Some(0x4C1);
+        tracer.record(
+            13,
+            Some(store.to_bytes()),
+            &store_event,
+            &regs,
+            (None, Some(-2)),
+        );
+
+        let call = Verb::Call(Operand::Imm(0x1C));
+        let mut call_event = event(0x20, call);
+        call_event.jump_taken = true;
+        call_event.interrupt = true;
+        tracer.record(14, None, &call_event, &regs, (None, None));
+
+        tracer.flush().unwrap();
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn text_records() {
+        assert_eq!(
+            trace(TraceFormat::Text, Vec::new()),
+            [
+                "      12  0006:  mov R2 [0x4C0]         R2=0xFFFE [0x04C0]->0xFFFE",
+                "      13  0007:  mov [0x4C1] R2         [0x04C1]<-0xFFFE",
+                "      14  0020:  interrupt: call 0x1C   (jump)",
+            ]
+        );
+        assert_eq!(trace(TraceFormat::Text, vec![(7, 0x10)]).len(), 1);
+    }
+
+    #[test]
+    fn json_records() {
+        let lines = trace(TraceFormat::Json, Vec::new());
+        let records: Vec<serde_json::Value> = lines
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let hex = |verb: Verb| {
+            let word = verb.to_bytes();
+            format!("{:0>2x}{:0>2x}{:0>2x}", word[0], word[1], word[2])
+        };
+        assert_eq!(
+            records,
+            [
+                json!({
+                    "n": 12, "ip": 6, "instr": "mov R2 [0x4C0]",
+                    "word": hex(Verb::Mov(Operand::Reg(Reg::R2), Operand::MemAtImm(0x4C0))),
+                    "reg": "R2", "value": -2, "mem_read": 0x4C0, "read_value": -2,
+                }),
+                json!({
+                    "n": 13, "ip": 7, "instr": "mov [0x4C1] R2",
+                    "word": hex(Verb::Mov(Operand::MemAtImm(0x4C1), Operand::Reg(Reg::R2))),
+                    "mem_write": 0x4C1, "write_value": -2,
+                }),
+                json!({
+                    "n": 14, "ip": 0x20, "instr": "interrupt: call 0x1C",
+                    "jump": true, "interrupt": true,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_failed_write_ends_the_trace() {
+        let mut tracer = Tracer::create("/dev/full", TraceFormat::Text, Vec::new()).unwrap();
+        let event = event(0, Verb::Nop);
+        for count in 0..10_000 {
+            tracer.record(count, None, &event, &[0; 16], (None, None));
+        }
+        let error = tracer.flush().unwrap_err();
+        assert!(error.starts_with("could not write to trace file /dev/full"));
+        tracer.record(10_000, None, &event, &[0; 16], (None, None));
+        assert_eq!(tracer.flush(), Err(error));
+    }
+
+    #[test]
+    fn ranges() {
+        // .a calls .b, which has a loop label .b_loop, and .c follows
+        let code: Vec<[u8; 3]> = [
+            Verb::Call(Operand::Imm(2)),
+            Verb::Halt,
+            Verb::Nop,
+            Verb::Jmp(Operand::Imm(2)),
+            Verb::Call(Operand::Imm(5)),
+            Verb::Ret,
+        ]
+        .iter()
+        .map(Verb::to_bytes)
+        .collect();
+        let label_map = HashMap::from([
+            (".a".to_string(), 0),
+            (".b".to_string(), 2),
+            (".b_loop".to_string(), 3),
+            (".c".to_string(), 5),
+        ]);
+        let var_loc_map = HashMap::from([("LED_ADDR".to_string(), 0x4B4)]);
+        let parse = |s: &str| parse_trace_range(s, &code, &label_map, &var_loc_map);
+
+        assert_eq!(parse("0x10:0x20"), Ok((0x10, 0x20)));
+        assert_eq!(parse(".b:5"), Ok((2, 5)));
+        assert_eq!(parse("7"), Ok((7, 7)));
+        assert_eq!(parse("LED_ADDR"), Ok((0x4B4, 0x4B4)));
+        assert_eq!(parse(".a"), Ok((0, 1)));
+        // the loop label is not called, so it does not end .b
+        assert_eq!(parse(".b"), Ok((2, 4)));
+        assert_eq!(parse(".b_loop"), Ok((3, 4)));
+        assert_eq!(parse(".c"), Ok((5, u16::MAX)));
+        assert_eq!(
+            parse("0x20:0x10"),
+            Err("range start 0x20 is after its end 0x10".to_string())
+        );
+        assert_eq!(parse(".d"), Err("unknown location `.d`".to_string()));
+    }
+}
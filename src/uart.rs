@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+use std::io::Read;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+// The host's end of the UART: bytes waiting for the program to receive them, and bytes it sent
+// that have not been printed yet. Shared between CpuEmu and the Uart device.
+#[derive(Default)]
+pub struct UartPort {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+    // bytes the program has been given, so that reverse debugging can put them back into input
+    received: Vec<u8>,
+}
+
+impl UartPort {
+    pub fn receive(&mut self) -> Option<u8> {
+        let byte = self.input.pop_front()?;
+        self.received.push(byte);
+        Some(byte)
+    }
+
+    pub fn get_received_count(&self) -> usize {
+        self.received.len()
+    }
+
+    pub fn set_received_count(&mut self, count: usize) {
+        // returns the bytes received after the first `count` to the front of the input
+        while self.received.len() > count {
+            let byte = self.received.pop().unwrap();
+            self.input.push_front(byte);
+        }
+    }
+}
+
+pub fn read_uart_input(path: &str) -> Result<Vec<u8>, String> {
+    // the whole file, or everything on stdin up to its end for `-`
+    if path == "-" {
+        let mut bytes = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(|e| format!("could not read stdin: {}", e))?;
+        return Ok(bytes);
+    }
+    std::fs::read(path).map_err(|_| format!("could not open file: {}", path))
+}
+
+pub fn spawn_stdin_reader() -> Receiver<Vec<u8>> {
+    // for the window, which keeps running while waiting for input. Gives the bytes on stdin
+    // as they come in, usually a line at a time.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = std::io::stdin();
+        let mut buf = [0; 256];
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => {
+                    if sender.send(buf[..len].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn received_bytes_can_be_put_back() {
+        let mut port = UartPort::default();
+        port.input.extend(b"abc");
+        assert_eq!(port.receive(), Some(b'a'));
+        assert_eq!(port.receive(), Some(b'b'));
+        assert_eq!(port.get_received_count(), 2);
+
+        port.set_received_count(1);
+        assert_eq!(port.input, b"bc");
+        assert_eq!(port.receive(), Some(b'b'));
+        assert_eq!(port.receive(), Some(b'c'));
+        assert_eq!(port.receive(), None);
+        assert_eq!(port.get_received_count(), 3);
+
+        port.set_received_count(0);
+        assert_eq!(port.input, b"abc");
+    }
+}
@@ -0,0 +1,53 @@
+; Echoes what it receives over the UART in upper case, e.g.
+; `echo hello | cargo run -- uart.asm --headless --uart-input - --max-instructions 1000`.
+; Run its tests with `cargo run test uart.asm`.
+;
+;! test lower_case_a
+;!   set r1 0x61
+;!   call .to_upper
+;!   expect r1 0x41
+;! end
+;!
+;! test lower_case_z
+;!   set r1 0x7A
+;!   call .to_upper
+;!   expect r1 0x5A
+;! end
+;!
+;! test just_before_a_unchanged
+;!   set r1 0x60   # `
+;!   call .to_upper
+;!   expect r1 0x60
+;! end
+;!
+;! test just_past_z_unchanged
+;!   set r1 0x7B   # {
+;!   call .to_upper
+;!   expect r1 0x7B
+;! end
+
+mov r0 0x500
+
+.wait_for_byte
+  mov r1 [UART_STATUS_ADDR]
+  and r1 0x0001
+  jz .wait_for_byte r1
+  mov r1 [UART_RX_ADDR]
+  call .to_upper
+  mov [UART_TX_ADDR] r1
+jmp .wait_for_byte
+
+.to_upper
+  ; parameter and result: r1 = a character. Uses r2.
+  ; there is no compare, so the range checks look at the sign of a subtraction
+  mov r2 r1
+  sub r2 0x61
+  and r2 0x8000
+  jnz .to_upper_done r2
+  mov r2 r1
+  sub r2 0x7B
+  and r2 0x8000
+  jz .to_upper_done r2
+  sub r1 0x20
+  .to_upper_done
+ret